@@ -1,5 +1,7 @@
+use std::time::Duration;
+
 use common::compiler::program;
-use common::eval::eval;
+use common::eval::{eval_with, EvalOptions};
 use common::expr::{Expr, Token};
 use common::planar;
 use num_bigint::BigInt;
@@ -21,8 +23,14 @@ fn decode_message(input: String) -> PyResult<String> {
 
 #[pyfunction]
 fn evaluate_message(input: String) -> PyResult<String> {
+    // Requests may come from anywhere, so bound the work like the server does.
+    let options = EvalOptions {
+        max_beta_reductions: Some(10_000_000),
+        timeout: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
     if let Ok(expr) = input.parse() {
-        if let Ok(expr) = eval(&expr) {
+        if let Ok(expr) = eval_with(&expr, &options) {
             if let Expr::String(s) = expr {
                 return Ok(s.to_string());
             }
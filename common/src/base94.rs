@@ -1,63 +1,205 @@
-use anyhow::{anyhow, bail, ensure, Result};
+//! The base-94 digits of the ICFP language, and the character set of its
+//! strings.
+//!
+//! A digit is one of the 94 printable ASCII characters from `!` to `~`. A
+//! string character is encoded as the digit of its index in `ALPHABET`, which
+//! is also the digit that `U#` and `U$` use to convert between strings and
+//! integers.
+
+use num_bigint::{BigInt, Sign};
+
+/// The characters of strings, in the order of their digits.
+pub const ALPHABET: &[u8; 94] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!\"#$%&'()*+,-./:;<=>?@[\\]^_`|~ \n";
+
+/// The index in `ALPHABET` of each ASCII character, or `NONE`.
+const INDEX: [u8; 128] = {
+    let mut index = [NONE; 128];
+    let mut i = 0;
+    while i < ALPHABET.len() {
+        index[ALPHABET[i] as usize] = i as u8;
+        i += 1;
+    }
+    index
+};
+
+const NONE: u8 = u8::MAX;
+
+/// The character that starts an escape sequence in `escape_str`.
+pub const ESCAPE: char = '\\';
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Base94Error {
+    /// A character that is not a base-94 digit.
+    InvalidDigit(char),
+    /// A number that does not fit in a single digit.
+    DigitOutOfRange(i64),
+    NegativeNumber(BigInt),
+    /// The characters outside of `ALPHABET`, with their byte offsets in the
+    /// string.
+    UnencodableChars(Vec<(usize, char)>),
+    /// A malformed escape sequence at the byte offset in the string.
+    InvalidEscape(usize),
+}
+
+impl std::fmt::Display for Base94Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Base94Error::InvalidDigit(c) => write!(f, "invalid base94 char: {c:?}"),
+            Base94Error::DigitOutOfRange(n) => write!(f, "invalid base94 number: {n}"),
+            Base94Error::NegativeNumber(n) => write!(f, "negative number: {n}"),
+            Base94Error::UnencodableChars(chars) => {
+                write!(f, "unencodable characters: ")?;
+                for (i, (offset, c)) in chars.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{c:?} at {offset}")?;
+                }
+                Ok(())
+            }
+            Base94Error::InvalidEscape(offset) => write!(f, "invalid escape sequence at {offset}"),
+        }
+    }
+}
+
+impl std::error::Error for Base94Error {}
+
+pub type Result<T> = std::result::Result<T, Base94Error>;
 
 pub fn decode_base94(c: char) -> Result<i64> {
     if ('!'..='~').contains(&c) {
         let n = c as i64 - '!' as i64;
         Ok(n)
     } else {
-        bail!("invalid base94 char: {c}")
+        Err(Base94Error::InvalidDigit(c))
     }
 }
 
 pub fn encode_base94(n: i64) -> Result<char> {
-    if n < 0 || n >= 94 {
-        bail!("invalid base94 number: {n}")
+    if !(0..94).contains(&n) {
+        return Err(Base94Error::DigitOutOfRange(n));
     }
     Ok((n + '!' as i64) as u8 as char)
 }
 
-const TBL: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789!\"#$%&'()*+,-./:;<=>?@[\\]^_`|~ \n";
+/// The index of `c` in `ALPHABET`.
+pub fn char_index(c: char) -> Option<usize> {
+    match INDEX.get(c as usize) {
+        Some(&i) if i != NONE => Some(i as usize),
+        _ => None,
+    }
+}
+
+pub fn is_encodable(c: char) -> bool {
+    char_index(c).is_some()
+}
 
 pub fn decode_char(c: char) -> Result<char> {
-    Ok(TBL[decode_base94(c)? as usize] as char)
+    Ok(ALPHABET[decode_base94(c)? as usize] as char)
 }
 
 pub fn encode_char(c: char) -> Result<char> {
-    let ix = TBL
-        .iter()
-        .position(|&x| x == c as u8)
-        .ok_or_else(|| anyhow!("invalid char"))?;
-    encode_base94(ix as i64)
+    match char_index(c) {
+        Some(i) => encode_base94(i as i64),
+        None => Err(Base94Error::UnencodableChars(vec![(0, c)])),
+    }
 }
 
 pub fn decode_str(s: &str) -> Result<String> {
     s.chars().map(decode_char).collect()
 }
 
+/// Encodes `s`, or reports all of its characters outside of `ALPHABET`.
 pub fn encode_str(s: &str) -> Result<String> {
-    s.chars().map(encode_char).collect()
+    let unencodable = unencodable_chars(s);
+    if !unencodable.is_empty() {
+        return Err(Base94Error::UnencodableChars(unencodable));
+    }
+    Ok(s.chars().map(|c| encode_char(c).unwrap()).collect())
+}
+
+/// The characters of `s` outside of `ALPHABET`, with their byte offsets.
+pub fn unencodable_chars(s: &str) -> Vec<(usize, char)> {
+    s.char_indices()
+        .filter(|&(_, c)| !is_encodable(c))
+        .collect()
 }
 
-pub fn decode_base94_int(s: &str) -> Result<i64> {
-    let mut ret = 0;
+/// Replaces the characters outside of `ALPHABET` with `replacement`.
+pub fn replace_unencodable(s: &str, replacement: char) -> String {
+    assert!(
+        is_encodable(replacement),
+        "unencodable replacement: {replacement:?}"
+    );
+    s.chars()
+        .map(|c| if is_encodable(c) { c } else { replacement })
+        .collect()
+}
+
+/// Rewrites the characters outside of `ALPHABET` as `\u(hex)`, and `\` as
+/// `\\`, so that the result can be encoded and `unescape_str` restores `s`.
+pub fn escape_str(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
     for c in s.chars() {
-        ret = ret * 94 + decode_base94(c)?;
+        if c == ESCAPE {
+            ret.push_str("\\\\");
+        } else if is_encodable(c) {
+            ret.push(c);
+        } else {
+            ret.push_str(&format!("\\u({:x})", c as u32));
+        }
     }
-    Ok(ret)
+    ret
 }
 
-pub fn encode_base94_int(n: i64) -> Result<String> {
-    ensure!(n >= 0);
-    if n == 0 {
-        return Ok("!".to_string());
+/// The inverse of `escape_str`.
+pub fn unescape_str(s: &str) -> Result<String> {
+    let mut ret = String::with_capacity(s.len());
+    let mut chars = s.char_indices();
+    while let Some((offset, c)) = chars.next() {
+        if c != ESCAPE {
+            ret.push(c);
+            continue;
+        }
+        let rest = &s[offset + 1..];
+        if rest.starts_with(ESCAPE) {
+            chars.next();
+            ret.push(ESCAPE);
+            continue;
+        }
+        let invalid = Base94Error::InvalidEscape(offset);
+        let hex = rest
+            .strip_prefix("u(")
+            .and_then(|rest| rest.split_once(')'))
+            .map(|(hex, _)| hex)
+            .ok_or_else(|| invalid.clone())?;
+        let c = u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or(invalid)?;
+        ret.push(c);
+        // Skips `u(`, the digits and `)`.
+        for _ in 0..hex.len() + 3 {
+            chars.next();
+        }
     }
-    let mut s = String::new();
-    let mut n = n;
-    while n > 0 {
-        s.push(encode_base94(n % 94)?);
-        n /= 94;
+    Ok(ret)
+}
+
+pub fn decode_base94_int(s: &str) -> Result<BigInt> {
+    let digits = s
+        .chars()
+        .map(|c| decode_base94(c).map(|d| d as u8))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(BigInt::from_radix_be(Sign::Plus, &digits, 94).unwrap_or_default())
+}
+
+pub fn encode_base94_int(n: &BigInt) -> Result<String> {
+    if n.sign() == Sign::Minus {
+        return Err(Base94Error::NegativeNumber(n.clone()));
     }
-    Ok(s.chars().rev().collect::<String>())
+    let (_, digits) = n.to_radix_be(94);
+    Ok(digits.into_iter().map(|d| (b'!' + d) as char).collect())
 }
 
 #[cfg(test)]
@@ -66,7 +208,54 @@ mod tests {
 
     #[test]
     fn encode_decode_int() {
-        assert_eq!(decode_base94_int("/6").unwrap(), 1337);
-        assert_eq!(encode_base94_int(1337).unwrap(), "/6");
+        assert_eq!(decode_base94_int("/6").unwrap(), 1337.into());
+        assert_eq!(encode_base94_int(&1337.into()).unwrap(), "/6");
+        assert_eq!(encode_base94_int(&0.into()).unwrap(), "!");
+        assert_eq!(decode_base94_int("!").unwrap(), 0.into());
+        assert_eq!(
+            encode_base94_int(&(-1).into()),
+            Err(Base94Error::NegativeNumber((-1).into()))
+        );
+    }
+
+    #[test]
+    fn large_int() {
+        // Beyond i64.
+        let n: BigInt = BigInt::from(94).pow(30) - 1;
+        let s = encode_base94_int(&n).unwrap();
+        assert_eq!(s, "~".repeat(30));
+        assert_eq!(decode_base94_int(&s).unwrap(), n);
+    }
+
+    #[test]
+    fn alphabet() {
+        for (i, &c) in ALPHABET.iter().enumerate() {
+            assert_eq!(char_index(c as char), Some(i));
+        }
+        assert_eq!(char_index('\t'), None);
+        assert_eq!(char_index('é'), None);
+    }
+
+    #[test]
+    fn unencodable() {
+        assert_eq!(encode_str("Hello World!").unwrap(), "B%,,/}Q/2,$_");
+        assert_eq!(
+            encode_str("caf\u{e9}\tok"),
+            Err(Base94Error::UnencodableChars(vec![(3, 'é'), (5, '\t')]))
+        );
+        assert_eq!(replace_unencodable("caf\u{e9}\tok", '?'), "caf??ok");
+    }
+
+    #[test]
+    fn escape() {
+        for s in ["plain", "a\\b", "caf\u{e9}", "\t\u{1f600}\\u(", ""] {
+            let escaped = escape_str(s);
+            assert!(unencodable_chars(&escaped).is_empty(), "{escaped}");
+            assert_eq!(unescape_str(&escaped).unwrap(), s);
+        }
+        assert_eq!(escape_str("caf\u{e9}\\"), "caf\\u(e9)\\\\");
+        assert_eq!(unescape_str("a\\x"), Err(Base94Error::InvalidEscape(1)));
+        assert_eq!(unescape_str("\\u(zz)"), Err(Base94Error::InvalidEscape(0)));
+        assert_eq!(unescape_str("\\u(41"), Err(Base94Error::InvalidEscape(0)));
     }
 }
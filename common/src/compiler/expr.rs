@@ -304,7 +304,7 @@ impl Expr {
             }
             Expr::Str(s) => res.push("S".to_string() + &encode_str(s).unwrap()),
             Expr::Num(n) => {
-                res.push(format!("I{}", encode_base94_int(*n as i64).unwrap()));
+                res.push(format!("I{}", encode_base94_int(&(*n).into()).unwrap()));
             }
         }
     }
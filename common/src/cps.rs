@@ -1,69 +1,246 @@
+//! Continuation-passing style conversion.
+//!
+//! A converted expression is a computation `λk. ...` that passes the result
+//! to the continuation `k`. Every step of the evaluation is then an explicit
+//! call-by-value application (`B!`) of a lambda to a value, so the program
+//! needs no stack and its beta reductions can be counted from the source.
+//!
+//! Variables are bound to computations instead of values, which keeps the
+//! call-by-name semantics of `B$`: the argument is converted and passed
+//! without being run, and run again at each reference. `B~` is converted the
+//! same way, and gives the same result without sharing the work. `B!` runs
+//! the argument first and passes a computation returning its value.
+
 use std::rc::Rc;
 
 use crate::expr::{BinOp, Expr};
 
+/// Converts `e` to a computation that takes a continuation. Free variables of
+/// `e` are expected to be bound to computations.
 pub fn cps_conversion(e: &Expr) -> Expr {
-    cps_conv_internal(e)
+    let mut fresh = Fresh(max_var(e).map_or(0, |v| v + 1));
+    cps_conv_internal(e, &mut fresh)
+}
+
+/// Converts `e` and runs it with the identity continuation, which evaluates to
+/// the same integer, string or boolean as `e`.
+pub fn cps_program(e: &Expr) -> Expr {
+    let mut fresh = Fresh(max_var(e).map_or(0, |v| v + 1));
+    let c = cps_conv_internal(e, &mut fresh);
+    let x = fresh.next();
+    app(c, lambda(x, Expr::Var(x)))
+}
+
+/// Generates variables that do not occur in the original expression, so that
+/// they cannot capture its references.
+struct Fresh(usize);
+
+impl Fresh {
+    fn next(&mut self) -> usize {
+        self.0 += 1;
+        self.0 - 1
+    }
+}
+
+fn max_var(e: &Expr) -> Option<usize> {
+    match e {
+        Expr::Var(v) => Some(*v),
+        Expr::Un(_, e) => max_var(e),
+        Expr::Bin(_, l, r) => max_var(l).max(max_var(r)),
+        Expr::If(cond, th, el) => max_var(cond).max(max_var(th)).max(max_var(el)),
+        Expr::Lambda(v, body) => Some(*v).max(max_var(body)),
+        _ => None,
+    }
 }
 
-fn cps_conv_internal(e: &Expr) -> Expr {
+fn lambda(v: usize, body: Expr) -> Expr {
+    Expr::Lambda(v, Rc::new(body))
+}
+
+fn app(f: Expr, x: Expr) -> Expr {
+    Expr::Bin(BinOp::AppV, Rc::new(f), Rc::new(x))
+}
+
+/// `λk. k value`
+fn ret(value: Expr, fresh: &mut Fresh) -> Expr {
+    let k = fresh.next();
+    lambda(k, app(Expr::Var(k), value))
+}
+
+fn cps_conv_internal(e: &Expr, fresh: &mut Fresh) -> Expr {
     log::trace!("cps_conv: {e}");
 
     match e {
-        Expr::Bool(_) => e.clone(),
-        Expr::Int(_) => e.clone(),
-        Expr::String(_) => e.clone(),
-        Expr::Var(v) => Expr::Lambda(
-            200,
+        Expr::Bool(_) | Expr::Int(_) | Expr::String(_) => ret(e.clone(), fresh),
+        // λk. x k
+        Expr::Var(v) => {
+            let k = fresh.next();
+            lambda(k, app(Expr::Var(*v), Expr::Var(k)))
+        }
+        // λk. k (λx. [body])
+        Expr::Lambda(v, body) => {
+            let body = cps_conv_internal(body, fresh);
+            ret(lambda(*v, body), fresh)
+        }
+        // λk. [e] (λx. k (op x))
+        Expr::Un(op, e) => {
+            let e = cps_conv_internal(e, fresh);
+            let (k, x) = (fresh.next(), fresh.next());
+            let op = Expr::Un(*op, Rc::new(Expr::Var(x)));
+            lambda(k, app(e, lambda(x, app(Expr::Var(k), op))))
+        }
+        // λk. [f] (λg. g [arg] k)
+        Expr::Bin(BinOp::App | BinOp::AppL, f, arg) => {
+            let f = cps_conv_internal(f, fresh);
+            let arg = cps_conv_internal(arg, fresh);
+            let (k, g) = (fresh.next(), fresh.next());
+            let call = app(app(Expr::Var(g), arg), Expr::Var(k));
+            lambda(k, app(f, lambda(g, call)))
+        }
+        // λk. [f] (λg. [arg] (λx. g (λk'. k' x) k))
+        Expr::Bin(BinOp::AppV, f, arg) => {
+            let f = cps_conv_internal(f, fresh);
+            let arg = cps_conv_internal(arg, fresh);
+            let (k, g, x) = (fresh.next(), fresh.next(), fresh.next());
+            let call = app(app(Expr::Var(g), ret(Expr::Var(x), fresh)), Expr::Var(k));
+            lambda(k, app(f, lambda(g, app(arg, lambda(x, call)))))
+        }
+        // λk. [l] (λx. [r] (λy. k (x op y)))
+        Expr::Bin(op, l, r) => {
+            let l = cps_conv_internal(l, fresh);
+            let r = cps_conv_internal(r, fresh);
+            let (k, x, y) = (fresh.next(), fresh.next(), fresh.next());
+            let op = Expr::Bin(*op, Rc::new(Expr::Var(x)), Rc::new(Expr::Var(y)));
+            let inner = lambda(y, app(Expr::Var(k), op));
+            lambda(k, app(l, lambda(x, app(r, inner))))
+        }
+        // λk. [cond] (λb. if b then [th] k else [el] k)
+        Expr::If(cond, th, el) => {
+            let cond = cps_conv_internal(cond, fresh);
+            let th = cps_conv_internal(th, fresh);
+            let el = cps_conv_internal(el, fresh);
+            let (k, b) = (fresh.next(), fresh.next());
+            let branch = Expr::If(
+                Rc::new(Expr::Var(b)),
+                Rc::new(app(th, Expr::Var(k))),
+                Rc::new(app(el, Expr::Var(k))),
+            );
+            lambda(k, app(cond, lambda(b, branch)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{eval, eval_with_stats, EvalOptions};
+    use crate::expr::UnOp;
+
+    fn check(s: &str) {
+        let e: Expr = s.parse().unwrap();
+        let converted = cps_program(&e);
+        assert_eq!(eval(&converted).unwrap(), eval(&e).unwrap(), "{s}");
+    }
+
+    #[test]
+    fn operators() {
+        check("I$");
+        check("U- I$");
+        check("U! F");
+        check("U# S4%34");
+        check("U$ I4%34");
+        check("B+ I# B* I$ I%");
+        check("B. S4% S34");
+        check("BT I$ B. S4% S34");
+        check("B& T B| F T");
+        check("? B> I$ I# S4% S34");
+        check("? B< I$ I# S4% S34");
+    }
+
+    #[test]
+    fn applications() {
+        check("B$ L! B+ v! v! I$");
+        check("B~ L! B+ v! v! I$");
+        check("B! L! B+ v! v! I$");
+        check("B$ B$ L! L\" B- v! v\" I' I#");
+        // Functions as arguments and results.
+        check("B$ B$ L! L\" B$ v! B$ v! v\" L! B* v! I# I$");
+        // The example from the language specification.
+        check("B$ B$ L# L$ v# B. SB%,,/ S}Q/2,$_ IK");
+    }
+
+    #[test]
+    fn evaluation_order() {
+        // Call-by-name arguments are not evaluated unless they are used.
+        check("B$ L! I\" B+ T T");
+        check("B~ L! I\" B+ T T");
+        let e: Expr = "B! L! I\" B+ T T".parse().unwrap();
+        assert!(eval(&e).is_err());
+        assert!(eval(&cps_program(&e)).is_err());
+    }
+
+    #[test]
+    fn recursion() {
+        // The Y combinator relies on call-by-name.
+        check("B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B* v# B$ v\" B- v# I\" I&");
+    }
+
+    #[test]
+    fn no_capture() {
+        // (λ100. λ101. λ102. λ200. v100 - v101 * v102 + v200) 1 2 3 4, where
+        // the variables are the ones that the conversion used to introduce.
+        let var = |v: usize| Rc::new(Expr::Var(v));
+        let mut e = Expr::Bin(
+            BinOp::Add,
             Rc::new(Expr::Bin(
-                BinOp::AppV,
-                Rc::new(Expr::Var(200)),
-                Rc::new(Expr::Var(*v)),
+                BinOp::Sub,
+                var(100),
+                Rc::new(Expr::Bin(BinOp::Mul, var(101), var(102))),
             )),
-        ),
-        Expr::Un(_, _) => e.clone(),
-        Expr::Bin(op, l, r) => {
-            if matches!(op, BinOp::App) {
-                let l = cps_conv_internal(l);
-                let r = cps_conv_internal(r);
-                Expr::Lambda(
-                    100,
-                    Rc::new(Expr::Bin(
-                        BinOp::AppV,
-                        Rc::new(l),
-                        Rc::new(Expr::Lambda(
-                            101,
-                            Rc::new(Expr::Bin(
-                                BinOp::AppV,
-                                Rc::new(r),
-                                Rc::new(Expr::Lambda(
-                                    102,
-                                    Rc::new(Expr::Bin(
-                                        BinOp::AppV,
-                                        Rc::new(Expr::Bin(
-                                            BinOp::AppV,
-                                            Rc::new(Expr::Var(101)),
-                                            Rc::new(Expr::Var(102)),
-                                        )),
-                                        Rc::new(Expr::Var(100)),
-                                    )),
-                                )),
-                            )),
-                        )),
-                    )),
-                )
-            } else {
-                e.clone()
-            }
+            var(200),
+        );
+        for v in [200, 102, 101, 100] {
+            e = lambda(v, e);
+        }
+        for n in 1..=4 {
+            e = Expr::Bin(
+                BinOp::App,
+                Rc::new(e),
+                Rc::new(Expr::Int(Rc::new(n.into()))),
+            );
         }
-        Expr::If(_, _, _) => e.clone(),
-        Expr::Lambda(v, e) => {
-            let e = cps_conv_internal(e);
-            let lin = Rc::new(Expr::Lambda(*v, Rc::new(e)));
-            Expr::Lambda(
-                100,
-                Rc::new(Expr::Bin(BinOp::AppV, Rc::new(Expr::Var(100)), lin)),
-            )
+        check(&e.encoded().to_string());
+        let neg = Expr::Un(UnOp::Neg, var(100));
+        let e = app(
+            lambda(100, Expr::Bin(BinOp::Add, var(100), Rc::new(neg))),
+            Expr::Int(Rc::new(7.into())),
+        );
+        check(&e.encoded().to_string());
+    }
+
+    #[test]
+    fn strict() {
+        // Every application in the converted program is call-by-value, so
+        // it only takes lambdas and values.
+        fn applications(e: &Expr) -> bool {
+            match e {
+                Expr::Bin(op, l, r) => {
+                    (!matches!(op, BinOp::App | BinOp::AppL)) && applications(l) && applications(r)
+                }
+                Expr::Un(_, e) | Expr::Lambda(_, e) => applications(e),
+                Expr::If(cond, th, el) => {
+                    applications(cond) && applications(th) && applications(el)
+                }
+                _ => true,
+            }
         }
+        let e: Expr = "B$ L! B+ v! v! B~ L! v! I$".parse().unwrap();
+        let converted = cps_program(&e);
+        assert!(applications(&converted));
+        let (value, stats) = eval_with_stats(&converted, &EvalOptions::default()).unwrap();
+        assert_eq!(value, eval(&e).unwrap());
+        // Every reduction is spelled out in the converted program: one per
+        // computation run and one per continuation called.
+        assert_eq!(stats.beta_reductions, 22);
     }
 }
@@ -0,0 +1,389 @@
+//! Common subexpression extraction.
+//!
+//! Hand-written programs often repeat subterms, e.g. `(concat x x)` or a
+//! string literal. Binding such a term once with `B~ L! body shared` and
+//! referring to it by a variable makes the program shorter when the term is
+//! long or repeated often enough.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+use crate::expr::{BinOp, Expr};
+use crate::renumber::renumber_vars;
+
+/// Binds repeated subterms of `e` to variables while that makes its encoding
+/// shorter. Bindings are call-by-need (`B~`), so the shared term is evaluated
+/// at most as often as before, and not at all if no occurrence is evaluated.
+pub fn share_common_subexprs(e: &Expr) -> Expr {
+    let (mut cur, mut next) = unique_names(e);
+    let mut best = e.clone();
+    let mut best_size = e.size_in_bytes();
+    while let Some(candidate) = find_candidate(&cur, next) {
+        cur = extract(&cur, &candidate, next);
+        next += 1;
+        // The estimate assumed single-byte variables, so check the real size.
+        let renumbered = renumber_vars(&cur).expr;
+        let size = renumbered.size_in_bytes();
+        if size >= best_size {
+            break;
+        }
+        best = renumbered;
+        best_size = size;
+    }
+    best
+}
+
+/// Renames the binders of `e` apart from each other and the free variables,
+/// so that equal variables refer to the same binder everywhere. Returns the
+/// renamed term and an index that is not used in it.
+fn unique_names(e: &Expr) -> (Expr, usize) {
+    fn go(e: &Expr, env: &mut Vec<(usize, usize)>, next: &mut usize) -> Expr {
+        match e {
+            Expr::Var(v) => Expr::Var(
+                env.iter()
+                    .rev()
+                    .find(|(old, _)| old == v)
+                    .map_or(*v, |(_, new)| *new),
+            ),
+            Expr::Un(op, e) => Expr::Un(*op, go(e, env, next).into()),
+            Expr::Bin(op, l, r) => {
+                let l = go(l, env, next);
+                let r = go(r, env, next);
+                Expr::Bin(*op, l.into(), r.into())
+            }
+            Expr::If(cond, th, el) => {
+                let cond = go(cond, env, next);
+                let th = go(th, env, next);
+                let el = go(el, env, next);
+                Expr::If(cond.into(), th.into(), el.into())
+            }
+            Expr::Lambda(v, body) => {
+                let new = *next;
+                *next += 1;
+                env.push((*v, new));
+                let body = go(body, env, next);
+                env.pop();
+                Expr::Lambda(new, body.into())
+            }
+            _ => e.clone(),
+        }
+    }
+
+    let mut next = e.free_vars().last().map_or(0, |v| v + 1);
+    let e = go(e, &mut vec![], &mut next);
+    (e, next)
+}
+
+/// The subterms of a term in pre-order, so that a subterm is identified by
+/// its index.
+struct Nodes<'a> {
+    exprs: Vec<&'a Expr>,
+    parents: Vec<usize>,
+    depths: Vec<usize>,
+    /// Hashes of the shapes of the subterms, which ignore the variables and
+    /// so are equal for subterms equal up to their own binders.
+    hashes: Vec<u64>,
+    /// The lengths of the encodings if all the variables had single-digit
+    /// indices, as most do after renumbering. Unlike the actual lengths, they
+    /// are equal for subterms equal up to their own binders.
+    sizes: Vec<usize>,
+}
+
+impl<'a> Nodes<'a> {
+    fn new(e: &'a Expr) -> Self {
+        let mut nodes = Nodes {
+            exprs: vec![],
+            parents: vec![],
+            depths: vec![],
+            hashes: vec![],
+            sizes: vec![],
+        };
+        nodes.add(e, 0, 0);
+        nodes
+    }
+
+    /// Adds `e` and its subterms, and returns its hash and size.
+    fn add(&mut self, e: &'a Expr, parent: usize, depth: usize) -> (u64, usize) {
+        let id = self.exprs.len();
+        self.exprs.push(e);
+        self.parents.push(parent);
+        self.depths.push(depth);
+        self.hashes.push(0);
+        self.sizes.push(0);
+
+        let mut hasher = DefaultHasher::new();
+        std::mem::discriminant(e).hash(&mut hasher);
+        let size = match e {
+            Expr::Bool(b) => {
+                b.hash(&mut hasher);
+                1
+            }
+            Expr::Int(n) => {
+                n.hash(&mut hasher);
+                e.size_in_bytes()
+            }
+            Expr::String(s) => {
+                s.hash(&mut hasher);
+                1 + s.chars().count()
+            }
+            Expr::Var(_) => 2,
+            Expr::Un(op, _) => {
+                op.hash(&mut hasher);
+                2
+            }
+            Expr::Bin(op, _, _) => {
+                op.hash(&mut hasher);
+                2
+            }
+            Expr::If(..) => 1,
+            Expr::Lambda(..) => 2,
+        };
+        let mut size = size;
+        for child in e.children() {
+            let (h, n) = self.add(child, id, depth + 1);
+            h.hash(&mut hasher);
+            size += n + 1;
+        }
+        let hash = hasher.finish();
+        self.hashes[id] = hash;
+        self.sizes[id] = size;
+        (hash, size)
+    }
+
+    fn lca(&self, mut a: usize, mut b: usize) -> usize {
+        while a != b {
+            if self.depths[a] >= self.depths[b] {
+                a = self.parents[a];
+            } else {
+                b = self.parents[b];
+            }
+        }
+        a
+    }
+}
+
+/// A repeated subterm, with the indices of its occurrences and of the node to
+/// bind it at.
+struct Candidate {
+    occurrences: HashSet<usize>,
+    binding: usize,
+}
+
+/// Finds the repeated subterm whose extraction is estimated to save the most
+/// bytes, if any saves some. Variables of `e` are below `base`.
+fn find_candidate(e: &Expr, base: usize) -> Option<Candidate> {
+    let nodes = Nodes::new(e);
+    let mut groups: HashMap<(u64, usize), Vec<usize>> = HashMap::new();
+    for (id, sub) in nodes.exprs.iter().enumerate() {
+        // `v!` is two bytes, so shorter terms cannot be worth it.
+        if !matches!(sub, Expr::Var(_)) && nodes.sizes[id] > 2 {
+            let key = (nodes.hashes[id], nodes.sizes[id]);
+            groups.entry(key).or_default().push(id);
+        }
+    }
+
+    // Each occurrence becomes a two-byte variable, and `B~ L! ` plus a space
+    // and the shared term are added once.
+    let saving = |size: usize, count: usize| (count * (size - 2)) as isize - (size + 7) as isize;
+    let mut groups: Vec<(isize, Vec<usize>)> = groups
+        .into_iter()
+        .map(|((_, size), ids)| (saving(size, ids.len()), ids))
+        .filter(|(saving, _)| *saving > 0)
+        .collect();
+    // Ties go to the first subterm in pre-order, to make the result
+    // deterministic.
+    groups.sort_by_key(|(saving, ids)| (std::cmp::Reverse(*saving), ids[0]));
+
+    // Subterms with the same hash have the same shape, but their variables
+    // may differ, so split them by the actual terms. The estimate of a group
+    // bounds the savings of its parts.
+    let mut best: Option<(isize, Vec<usize>)> = None;
+    for (estimate, ids) in groups {
+        if best.as_ref().is_some_and(|(saving, _)| *saving >= estimate) {
+            break;
+        }
+        let mut split: HashMap<String, Vec<usize>> = HashMap::new();
+        for id in ids {
+            let key = canonical(nodes.exprs[id], base, &mut vec![])
+                .encoded()
+                .to_string();
+            split.entry(key).or_default().push(id);
+        }
+        let mut split: Vec<Vec<usize>> = split.into_values().collect();
+        split.sort();
+        for ids in split {
+            let saving = saving(nodes.sizes[ids[0]], ids.len());
+            if saving > 0 && best.as_ref().is_none_or(|(best, _)| saving > *best) {
+                best = Some((saving, ids));
+            }
+        }
+    }
+    let (_, ids) = best?;
+
+    // The variables of the lambdas above all the occurrences may be free in
+    // the term, so bind it inside them. Binders are unique, so the term does
+    // not refer to any other lambda above the occurrences. The body of a
+    // lambda comes right after it.
+    let mut binding = ids[1..].iter().fold(ids[0], |a, &b| nodes.lca(a, b));
+    while let Expr::Lambda(..) = nodes.exprs[binding] {
+        binding += 1;
+    }
+    Some(Candidate {
+        occurrences: ids.into_iter().collect(),
+        binding,
+    })
+}
+
+/// Replaces the occurrences of the candidate with `var` and binds it.
+fn extract(e: &Expr, candidate: &Candidate, var: usize) -> Expr {
+    let mut shared = None;
+    rewrite(e, &mut 0, &mut |id, e| {
+        if candidate.occurrences.contains(&id) {
+            shared.get_or_insert(e);
+            return Expr::Var(var);
+        }
+        if id == candidate.binding {
+            return Expr::Bin(
+                BinOp::AppL,
+                Expr::Lambda(var, e.into()).into(),
+                shared.take().unwrap().into(),
+            );
+        }
+        e
+    })
+}
+
+/// Rebuilds `e` bottom-up, passing each rebuilt node and its pre-order index
+/// to `f`.
+fn rewrite(e: &Expr, next: &mut usize, f: &mut impl FnMut(usize, Expr) -> Expr) -> Expr {
+    let id = *next;
+    *next += 1;
+    let mut go = |child: &Rc<Expr>, f: &mut _| -> Rc<Expr> { rewrite(child, next, f).into() };
+    let rebuilt = match e {
+        Expr::Un(op, x) => Expr::Un(*op, go(x, f)),
+        Expr::Bin(op, l, r) => {
+            let l = go(l, f);
+            Expr::Bin(*op, l, go(r, f))
+        }
+        Expr::If(cond, th, el) => {
+            let cond = go(cond, f);
+            let th = go(th, f);
+            Expr::If(cond, th, go(el, f))
+        }
+        Expr::Lambda(v, body) => Expr::Lambda(*v, go(body, f)),
+        _ => e.clone(),
+    };
+    f(id, rebuilt)
+}
+
+/// Renames the binders in `e` by their depth, starting from `base`, which is
+/// above every variable in the whole term. Two subterms have the same
+/// canonical form iff they are equal up to their own binders.
+fn canonical(e: &Expr, base: usize, env: &mut Vec<usize>) -> Expr {
+    match e {
+        Expr::Var(v) => match env.iter().rposition(|w| w == v) {
+            Some(depth) => Expr::Var(base + depth),
+            None => e.clone(),
+        },
+        Expr::Un(op, x) => Expr::Un(*op, canonical(x, base, env).into()),
+        Expr::Bin(op, l, r) => Expr::Bin(
+            *op,
+            canonical(l, base, env).into(),
+            canonical(r, base, env).into(),
+        ),
+        Expr::If(cond, th, el) => Expr::If(
+            canonical(cond, base, env).into(),
+            canonical(th, base, env).into(),
+            canonical(el, base, env).into(),
+        ),
+        Expr::Lambda(v, body) => {
+            let depth = env.len();
+            env.push(*v);
+            let body = canonical(body, base, env);
+            env.pop();
+            Expr::Lambda(base + depth, body.into())
+        }
+        _ => e.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::eval;
+
+    fn shared(s: &str) -> String {
+        let e: Expr = s.parse().unwrap();
+        let ret = share_common_subexprs(&e);
+        assert!(ret.size_in_bytes() <= e.size_in_bytes(), "{s} grew");
+        assert_eq!(eval(&ret).unwrap(), eval(&e).unwrap(), "{s}");
+        ret.encoded().to_string()
+    }
+
+    #[test]
+    fn nodes() {
+        let e: Expr = "B$ L! B. v! v! U$ ? T I\" I#".parse().unwrap();
+        let nodes = Nodes::new(&e);
+        for (id, sub) in nodes.exprs.iter().enumerate() {
+            assert_eq!(nodes.sizes[id], sub.size_in_bytes(), "{sub}");
+        }
+        // λ". v" and λ#. v# are the same. The hashes do not tell which
+        // binders the variables refer to, so find_candidate compares the
+        // terms.
+        let e: Expr = "L! B$ B$ L\" v\" L# v# B. v! S!".parse().unwrap();
+        let nodes = Nodes::new(&e);
+        assert_eq!(nodes.hashes[3], nodes.hashes[5]);
+        assert_eq!(nodes.hashes[4], nodes.hashes[8]);
+        assert_ne!(nodes.hashes[4], nodes.hashes[9]);
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            shared("B. SLLLLLLLL B. SLLLLLLLL SLLLLLLLL"),
+            "B~ L! B. v! B. v! v! SLLLLLLLL"
+        );
+        // Too short to be worth a binding.
+        assert_eq!(shared("B. SLL B. SLL SLL"), "B. SLL B. SLL SLL");
+    }
+
+    #[test]
+    fn open_terms() {
+        // (λx. (x ++ x ++ "L") ++ (x ++ x ++ "L")) "R": the binding goes
+        // inside the lambda of x.
+        assert_eq!(
+            shared("B$ L! B. B. B. v! v! SL B. B. v! v! SL SR"),
+            "B$ L! B~ L\" B. v\" v\" B. B. v! v! SL SR"
+        );
+    }
+
+    #[test]
+    fn alpha_equivalent() {
+        // The same function written with different variables.
+        assert_eq!(
+            shared("B$ B$ L! L\" B. B$ v! SA B$ v\" SB L# B. v# B. v# v# L$ B. v$ B. v$ v$"),
+            "B~ L% B$ B$ L! L\" B. B$ v! SA B$ v\" SB v% v% L# B. v# B. v# v#"
+        );
+    }
+
+    #[test]
+    fn scopes() {
+        // v! ++ v! is repeated often enough to be shared, but v" ++ v" is
+        // not, and neither is shared across the binders.
+        assert_eq!(
+            shared(
+                "B$ B$ L! L\" B. B. B. v! v! B. B. v! v! B. v! v! B. B. v\" v\" B. v\" v\" SA SB"
+            ),
+            "B$ B$ L! L\" B. B~ L# B. v# B. v# v# B. v! v! B. B. v\" v\" B. v\" v\" SA SB"
+        );
+
+        // The same subterm under two different lambdas is shared in each, and
+        // then so is the function binding it.
+        assert_eq!(
+            shared("B. B$ L! B. B. v! v! B. B. v! v! B. v! v! SA B$ L\" B. SC B. B. v\" v\" B. B. v\" v\" B. v\" v\" SB"),
+            "B~ L% B. B$ L! B~ v% B. v! v! SA B$ L\" B. SC B~ v% B. v\" v\" SB L# B. v# B. v# v#"
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! A macro for writing ICFP expressions in a Lisp-like syntax.
+//!
+//! ```
+//! use common::icfp;
+//!
+//! let header = "solve lambdaman6 ";
+//! let e = icfp! {
+//!     let x = "RRRR" in
+//!     (concat (#header) (concat x x))
+//! };
+//! assert_eq!(
+//!     e.encoded().to_string(),
+//!     r#"B$ L! B. S3/,6%},!-"$!-!.[} B. v! v! SLLLL"#
+//! );
+//! ```
+//!
+//! | Form                                    | Expression                         |
+//! |-----------------------------------------|------------------------------------|
+//! | `x`, `foo`                              | variables, see [`varid`]           |
+//! | `42`, `"str"`, `true`                   | literals, see [`ToExpr`]           |
+//! | `(#x)`, `(#(rust expr))`                | a Rust value converted by `ToExpr` |
+//! | `(fn x y -> body)`                      | `L x L y body`                     |
+//! | `(f x y)`                               | `B$ B$ f x y`                      |
+//! | `(~ f x y)`, `(! f x y)`                | the same with `B~` or `B!`         |
+//! | `let x = v in body`                     | `B$ L x body v`                    |
+//! | `let rec f = (fn x -> body) in e`       | `let f = (fix (fn f x -> body)) in e` |
+//! | `(fix f args...)`                       | the Y combinator applied to `f`    |
+//! | `(if c { t } else { e })`               | `? c t e`                          |
+//! | `(+ a b)`, `-`, `*`, `/`, `%`           | `B+ a b`, ...                      |
+//! | `(< a b)`, `>`, `==`, `&`, `\|`         | `B< a b`, ..., `B&`, `B\|`         |
+//! | `(concat a b)`, `take`, `drop`          | `B. a b`, `BT`, `BD`               |
+//! | `(neg a)`, `(- a)`, `(not a)`           | `U- a`, `U! a`                     |
+//! | `(to_int a)`, `(to_str a)`              | `U# a`, `U$ a`                     |
+//!
+//! `icfp! { lazy; ... }` uses `B~` instead of `B$` for applications and lets.
+
+use std::collections::BTreeSet;
+
+use num_bigint::BigInt;
+
+use crate::{base94::decode_base94_int, expr::Expr};
+
+/// Rust values that can be embedded in `icfp!` as literals or with `#`.
+pub trait ToExpr {
+    fn to_expr(&self) -> Expr;
+}
+
+impl ToExpr for Expr {
+    fn to_expr(&self) -> Expr {
+        self.clone()
+    }
+}
+
+impl ToExpr for bool {
+    fn to_expr(&self) -> Expr {
+        Expr::Bool(*self)
+    }
+}
+
+impl ToExpr for BigInt {
+    fn to_expr(&self) -> Expr {
+        Expr::Int(self.clone().into())
+    }
+}
+
+impl ToExpr for String {
+    fn to_expr(&self) -> Expr {
+        Expr::String(self.clone().into())
+    }
+}
+
+impl ToExpr for &str {
+    fn to_expr(&self) -> Expr {
+        Expr::String(self.to_string().into())
+    }
+}
+
+macro_rules! impl_to_expr_for_ints {
+    ($($t:ty)*) => {
+        $(
+            impl ToExpr for $t {
+                fn to_expr(&self) -> Expr {
+                    Expr::Int(BigInt::from(*self).into())
+                }
+            }
+        )*
+    };
+}
+
+impl_to_expr_for_ints!(i32 i64 i128 u32 u64 u128 usize);
+
+/// The variable for a name in `icfp!`: the one whose base-94 encoding is the
+/// name itself, so `foo` becomes `vfoo`. Distinct names never collide. Bound
+/// variables are then renumbered by [`allocate_vars`], so only free variables
+/// keep these numbers, and a term with a free `s` can be embedded under a
+/// `(fn s -> ...)` of another `icfp!`.
+pub fn varid(name: &str) -> usize {
+    decode_base94_int(name)
+        .unwrap()
+        .try_into()
+        .unwrap_or_else(|_| panic!("variable name too long to be encoded: {name}"))
+}
+
+/// Renumbers the bound variables of `e` to the smallest indices that do not
+/// change its meaning, which have the shortest encodings. A lambda may reuse
+/// the variable of an enclosing one that its body does not refer to, so
+/// sibling and nested scopes share indices. Free variables are kept.
+pub fn allocate_vars(e: &Expr) -> Expr {
+    fn go(e: &Expr, env: &mut Vec<(usize, usize)>) -> Expr {
+        let lookup = |env: &[(usize, usize)], v: usize| {
+            env.iter()
+                .rev()
+                .find(|(old, _)| *old == v)
+                .map_or(v, |(_, new)| *new)
+        };
+        match e {
+            Expr::Var(v) => Expr::Var(lookup(env, *v)),
+            Expr::Un(op, e) => Expr::Un(*op, go(e, env).into()),
+            Expr::Bin(op, l, r) => Expr::Bin(*op, go(l, env).into(), go(r, env).into()),
+            Expr::If(cond, th, el) => {
+                Expr::If(go(cond, env).into(), go(th, env).into(), go(el, env).into())
+            }
+            Expr::Lambda(v, body) => {
+                // The variables the new one must not capture.
+                let used: BTreeSet<usize> = body
+                    .free_vars()
+                    .into_iter()
+                    .filter(|w| w != v)
+                    .map(|w| lookup(env, w))
+                    .collect();
+                let new = (0..).find(|i| !used.contains(i)).unwrap();
+                env.push((*v, new));
+                let body = go(body, env);
+                env.pop();
+                Expr::Lambda(new, body.into())
+            }
+            _ => e.clone(),
+        }
+    }
+
+    go(e, &mut vec![])
+}
+
+#[macro_export]
+macro_rules! icfp {
+    (@$app:ident; fix) => {
+        $crate::icfp! { @$app; (fn f -> ((fn x -> (f (x x))) (fn x -> (f (x x))))) }
+    };
+    (@$app:ident; fix $($args:tt)+) => {
+        $crate::icfp! { @$app; ((fix) $($args)+) }
+    };
+    (@$app:ident; fn $($args:ident)+ -> $body:tt) => {{
+        let mut e = $crate::icfp! { @$app; $body };
+        for arg in [$(stringify!($args)),+].iter().rev() {
+            e = $crate::expr::Expr::Lambda($crate::dsl::varid(arg), ::std::rc::Rc::new(e));
+        }
+        e
+    }};
+    (@$app:ident; let rec $var:ident = $val:tt in $($body:tt)+) => {
+        $crate::icfp! { @$app; let $var = (fix (fn $var -> $val)) in $($body)+ }
+    };
+    (@$app:ident; let $var:ident = $val:tt in $($body:tt)+) => {
+        $crate::expr::Expr::Bin(
+            $crate::expr::BinOp::$app,
+            ::std::rc::Rc::new($crate::expr::Expr::Lambda(
+                $crate::dsl::varid(stringify!($var)),
+                ::std::rc::Rc::new($crate::icfp! { @$app; $($body)+ }),
+            )),
+            ::std::rc::Rc::new($crate::icfp! { @$app; $val }),
+        )
+    };
+    (@$app:ident; if $cond:tt { $($th:tt)+ } else { $($el:tt)+ }) => {
+        $crate::expr::Expr::If(
+            ::std::rc::Rc::new($crate::icfp! { @$app; $cond }),
+            ::std::rc::Rc::new($crate::icfp! { @$app; $($th)+ }),
+            ::std::rc::Rc::new($crate::icfp! { @$app; $($el)+ }),
+        )
+    };
+    (@$app:ident; concat $l:tt $r:tt) => { $crate::icfp! { @bin $app; Concat $l $r } };
+    (@$app:ident; take $l:tt $r:tt) => { $crate::icfp! { @bin $app; Take $l $r } };
+    (@$app:ident; drop $l:tt $r:tt) => { $crate::icfp! { @bin $app; Drop $l $r } };
+    (@$app:ident; + $l:tt $r:tt) => { $crate::icfp! { @bin $app; Add $l $r } };
+    (@$app:ident; - $l:tt $r:tt) => { $crate::icfp! { @bin $app; Sub $l $r } };
+    (@$app:ident; * $l:tt $r:tt) => { $crate::icfp! { @bin $app; Mul $l $r } };
+    (@$app:ident; / $l:tt $r:tt) => { $crate::icfp! { @bin $app; Div $l $r } };
+    (@$app:ident; % $l:tt $r:tt) => { $crate::icfp! { @bin $app; Mod $l $r } };
+    (@$app:ident; < $l:tt $r:tt) => { $crate::icfp! { @bin $app; Lt $l $r } };
+    (@$app:ident; > $l:tt $r:tt) => { $crate::icfp! { @bin $app; Gt $l $r } };
+    (@$app:ident; == $l:tt $r:tt) => { $crate::icfp! { @bin $app; Eq $l $r } };
+    (@$app:ident; & $l:tt $r:tt) => { $crate::icfp! { @bin $app; And $l $r } };
+    (@$app:ident; | $l:tt $r:tt) => { $crate::icfp! { @bin $app; Or $l $r } };
+    (@$app:ident; neg $e:tt) => { $crate::icfp! { @un $app; Neg $e } };
+    (@$app:ident; - $e:tt) => { $crate::icfp! { @un $app; Neg $e } };
+    (@$app:ident; not $e:tt) => { $crate::icfp! { @un $app; Not $e } };
+    (@$app:ident; to_int $e:tt) => { $crate::icfp! { @un $app; StrToInt $e } };
+    (@$app:ident; to_str $e:tt) => { $crate::icfp! { @un $app; IntToStr $e } };
+    (@$app:ident; # $var:ident) => {
+        $crate::dsl::ToExpr::to_expr(&$var)
+    };
+    (@$app:ident; # ($e:expr)) => {
+        $crate::dsl::ToExpr::to_expr(&$e)
+    };
+    (@$app:ident; ~ $f:tt $($args:tt)+) => { $crate::icfp! { @apply $app AppL; $f $($args)+ } };
+    (@$app:ident; ! $f:tt $($args:tt)+) => { $crate::icfp! { @apply $app AppV; $f $($args)+ } };
+    (@$app:ident; $f:tt $($args:tt)+) => { $crate::icfp! { @apply $app $app; $f $($args)+ } };
+    (@$app:ident; $val:literal) => {
+        $crate::dsl::ToExpr::to_expr(&$val)
+    };
+    (@$app:ident; $var:ident) => {
+        $crate::expr::Expr::Var($crate::dsl::varid(stringify!($var)))
+    };
+    (@$app:ident; ($($tt:tt)+)) => {
+        $crate::icfp! { @$app; $($tt)+ }
+    };
+
+    (@bin $app:ident; $op:ident $l:tt $r:tt) => {
+        $crate::expr::Expr::Bin(
+            $crate::expr::BinOp::$op,
+            ::std::rc::Rc::new($crate::icfp! { @$app; $l }),
+            ::std::rc::Rc::new($crate::icfp! { @$app; $r }),
+        )
+    };
+    (@un $app:ident; $op:ident $e:tt) => {
+        $crate::expr::Expr::Un(
+            $crate::expr::UnOp::$op,
+            ::std::rc::Rc::new($crate::icfp! { @$app; $e }),
+        )
+    };
+    (@apply $app:ident $op:ident; $f:tt $($args:tt)+) => {{
+        let mut e = $crate::icfp! { @$app; $f };
+        $(
+            e = $crate::expr::Expr::Bin(
+                $crate::expr::BinOp::$op,
+                ::std::rc::Rc::new(e),
+                ::std::rc::Rc::new($crate::icfp! { @$app; $args }),
+            );
+        )+
+        e
+    }};
+
+    (lazy; $($tt:tt)+) => {
+        $crate::dsl::allocate_vars(&$crate::icfp! { @AppL; $($tt)+ })
+    };
+    ($($tt:tt)+) => {
+        $crate::dsl::allocate_vars(&$crate::icfp! { @App; $($tt)+ })
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(e: Expr) -> String {
+        e.encoded().to_string()
+    }
+
+    #[test]
+    fn atoms() {
+        assert_eq!(encoded(icfp! { x }), "vx");
+        assert_eq!(encoded(icfp! { foo_1 }), "vfoo_1");
+        assert_eq!(encoded(icfp! { 1337 }), "I/6");
+        assert_eq!(encoded(icfp! { 18446744073709551557_i128 }), "IA33?&-jqQ.");
+        assert_eq!(encoded(icfp! { "Hello World!" }), "SB%,,/}Q/2,$_");
+        assert_eq!(encoded(icfp! { true }), "T");
+
+        let n = BigInt::from(2);
+        let s = "a".to_string();
+        assert_eq!(encoded(icfp! { (+ (#n) (#(3_u64 * 4))) }), "B+ I# I-");
+        assert_eq!(encoded(icfp! { (concat (#s) x) }), "B. S! vx");
+    }
+
+    #[test]
+    fn operators() {
+        for (e, expected) in [
+            (icfp! { (+ a b) }, "B+ va vb"),
+            (icfp! { (- a b) }, "B- va vb"),
+            (icfp! { (* a b) }, "B* va vb"),
+            (icfp! { (/ a b) }, "B/ va vb"),
+            (icfp! { (% a b) }, "B% va vb"),
+            (icfp! { (< a b) }, "B< va vb"),
+            (icfp! { (> a b) }, "B> va vb"),
+            (icfp! { (== a b) }, "B= va vb"),
+            (icfp! { (& a b) }, "B& va vb"),
+            (icfp! { (| a b) }, "B| va vb"),
+            (icfp! { (concat a b) }, "B. va vb"),
+            (icfp! { (take a b) }, "BT va vb"),
+            (icfp! { (drop a b) }, "BD va vb"),
+            (icfp! { (neg a) }, "U- va"),
+            (icfp! { (- a) }, "U- va"),
+            (icfp! { (not a) }, "U! va"),
+            (icfp! { (to_int a) }, "U# va"),
+            (icfp! { (to_str a) }, "U$ va"),
+        ] {
+            assert_eq!(encoded(e), expected);
+        }
+    }
+
+    #[test]
+    fn application() {
+        assert_eq!(encoded(icfp! { (f x y) }), "B$ B$ vf vx vy");
+        assert_eq!(encoded(icfp! { (~ f x y) }), "B~ B~ vf vx vy");
+        assert_eq!(encoded(icfp! { (! f (g x)) }), "B! vf B$ vg vx");
+        assert_eq!(encoded(icfp! { lazy; (f (g x)) }), "B~ vf B~ vg vx");
+    }
+
+    #[test]
+    fn binders() {
+        assert_eq!(encoded(icfp! { (fn x y -> (+ x y)) }), r#"L! L" B+ v! v""#);
+        assert_eq!(
+            encoded(icfp! { let x = 1 in (+ x x) }),
+            r#"B$ L! B+ v! v! I""#
+        );
+        assert_eq!(encoded(icfp! { lazy; let x = 1 in x }), r#"B~ L! v! I""#);
+        assert_eq!(
+            encoded(icfp! { (if (< x 0) { (neg x) } else { x }) }),
+            "? B< vx I! U- vx vx"
+        );
+
+        let y = r#"L! B$ L" B$ v! B$ v" v" L" B$ v! B$ v" v""#;
+        assert_eq!(encoded(icfp! { fix }), y);
+        assert_eq!(
+            encoded(icfp! { (fix (fn f n -> n) 1) }),
+            format!(r#"B$ B$ {y} L! L! v! I""#)
+        );
+        assert_eq!(
+            encoded(icfp! { let rec f = (fn n -> (f n)) in (f 1) }),
+            format!(r#"B$ L! B$ v! I" B$ {y} L! L" B$ v! v""#)
+        );
+    }
+
+    #[test]
+    fn allocation() {
+        // Names with the same first letter are different variables.
+        assert_eq!(
+            encoded(icfp! { (fn f foo -> (f foo)) }),
+            r#"L! L" B$ v! v""#
+        );
+        // Sibling scopes and unused outer variables share indices.
+        assert_eq!(
+            encoded(icfp! { (fn a -> ((fn b -> b) (fn c -> (c a)))) }),
+            r#"L! B$ L! v! L" B$ v" v!"#
+        );
+        assert_eq!(encoded(icfp! { (fn a b -> b) }), "L! L! v!");
+        // Shadowing keeps referring to the nearest binder.
+        assert_eq!(
+            encoded(icfp! { let x = 1 in let x = (+ x 1) in x }),
+            r#"B$ L! B$ L! v! B+ v! I" I""#
+        );
+
+        // A term with free variables can be embedded, and the variables are
+        // bound by the enclosing lambdas.
+        let step = icfp! { (fn a -> (+ a s)) };
+        assert_eq!(encoded(step.clone()), "L! B+ v! vs");
+        let e = icfp! { (fn s -> ((#step) s)) };
+        assert_eq!(encoded(e.clone()), r#"L! B$ L" B+ v" v! v!"#);
+        assert_eq!(crate::eval::eval(&icfp! { ((#e) 2) }).unwrap(), icfp! { 4 });
+    }
+
+    #[test]
+    fn evaluates() {
+        let e = icfp! {
+            let rec fact = (fn n -> (if (== n 0) { 1 } else { (* n (fact (- n 1))) })) in
+            (fact 10)
+        };
+        assert_eq!(crate::eval::eval(&e).unwrap(), icfp! { 3628800 });
+    }
+}
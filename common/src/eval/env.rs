@@ -0,0 +1,360 @@
+//! Environment-based evaluator.
+//!
+//! Instead of rewriting lambda bodies on every application, arguments are
+//! bound in a persistent environment and lambdas evaluate to closures. The
+//! evaluator is a CEK machine, so it does not recurse on the Rust stack. Every
+//! argument is memoized once it is forced; for call-by-name (`B$`) arguments
+//! the work spent on the first force is charged again on each later force, so
+//! the statistics match the substitution evaluator. That work excludes the
+//! first force of call-by-need (`B~`) arguments bound outside the forced one:
+//! the substitution evaluator shares them with every copy.
+
+use std::{cell::RefCell, rc::Rc};
+
+use super::{apply_bin, apply_un, error::type_mismatch, Counters, EvalError, Meter, Operator};
+use crate::expr::{BinOp, Expr, UnOp};
+
+#[derive(Clone, Debug)]
+enum Value {
+    /// Bool, Int or String.
+    Lit(Expr),
+    Closure(usize, Rc<Expr>, Env),
+}
+
+type Env = Option<Rc<Binding>>;
+
+#[derive(Debug)]
+struct Binding {
+    var: usize,
+    thunk: Rc<Thunk>,
+    next: Env,
+}
+
+fn lookup(env: &Env, v: usize) -> Option<&Rc<Thunk>> {
+    let mut env = env;
+    while let Some(b) = env {
+        if b.var == v {
+            return Some(&b.thunk);
+        }
+        env = &b.next;
+    }
+    None
+}
+
+fn bind(env: &Env, var: usize, thunk: Rc<Thunk>) -> Env {
+    Some(Rc::new(Binding {
+        var,
+        thunk,
+        next: env.clone(),
+    }))
+}
+
+#[derive(Debug)]
+struct Thunk {
+    /// True for call-by-need arguments, whose cost is paid only once.
+    shared: bool,
+    /// When the thunk was created, on the clock of `run`.
+    created: u64,
+    state: RefCell<ThunkState>,
+}
+
+#[derive(Clone, Debug)]
+enum ThunkState {
+    Delayed(Rc<Expr>, Env),
+    /// The value and, unless the thunk is shared, the work it took to compute
+    /// it.
+    Forced(Value, Option<Rc<Counters>>),
+}
+
+impl Thunk {
+    fn delayed(e: Rc<Expr>, env: Env, shared: bool, created: u64) -> Rc<Thunk> {
+        Rc::new(Thunk {
+            shared,
+            created,
+            state: RefCell::new(ThunkState::Delayed(e, env)),
+        })
+    }
+
+    fn forced(v: Value) -> Rc<Thunk> {
+        Rc::new(Thunk {
+            shared: true,
+            created: 0,
+            state: RefCell::new(ThunkState::Forced(v, None)),
+        })
+    }
+}
+
+// Unevaluated arguments can form long chains of thunks referring to each
+// other through their environments, which would overflow the stack if dropped
+// recursively. They are unlinked here with an explicit stack instead.
+impl Drop for Thunk {
+    fn drop(&mut self) {
+        let mut envs = vec![];
+        take_env(self.state.get_mut(), &mut envs);
+        while let Some(env) = envs.pop() {
+            let Ok(binding) = Rc::try_unwrap(env) else {
+                continue;
+            };
+            let Binding { thunk, next, .. } = binding;
+            envs.extend(next);
+            if let Ok(mut thunk) = Rc::try_unwrap(thunk) {
+                take_env(thunk.state.get_mut(), &mut envs);
+            }
+        }
+    }
+}
+
+fn take_env(state: &mut ThunkState, envs: &mut Vec<Rc<Binding>>) {
+    match state {
+        ThunkState::Delayed(_, env) | ThunkState::Forced(Value::Closure(_, _, env), _) => {
+            envs.extend(env.take())
+        }
+        ThunkState::Forced(Value::Lit(_), _) => {}
+    }
+}
+
+/// What the machine does once the current value is computed.
+enum Frame {
+    /// Apply the value (a closure) to the argument.
+    Apply(BinOp, Rc<Expr>, Env),
+    /// Bind the value to the variable of the closure and run its body. Used
+    /// for call-by-value arguments.
+    Call(usize, Rc<Expr>, Env),
+    Un(UnOp),
+    /// Evaluate the right operand after the left one.
+    BinLeft(BinOp, Rc<Expr>, Env),
+    BinRight(BinOp, Expr),
+    If(Rc<Expr>, Rc<Expr>, Env),
+    /// Memoize the value in the thunk. The matching `Forcing` is on top of
+    /// the forcing stack.
+    Update(Rc<Thunk>),
+}
+
+/// A thunk being forced.
+struct Forcing {
+    /// The counters when the thunk was entered.
+    start: Counters,
+    /// When the thunk was entered.
+    entered: u64,
+    /// When the thunk was created, if it is shared.
+    shared: Option<u64>,
+    /// The work done in the first forces of shared thunks created before this
+    /// one was entered, which would not be repeated by forcing it again.
+    excluded: Counters,
+}
+
+enum Control {
+    Eval(Rc<Expr>, Env),
+    Return(Value),
+}
+
+pub(super) fn eval(e: &Expr, meter: &mut Meter) -> Result<Expr, EvalError> {
+    let v = run(Rc::new(e.clone()), meter)?;
+    Ok(read_back(&v))
+}
+
+/// Runs a CEK machine. The continuation is kept in a heap-allocated stack, so
+/// deep recursion in the program does not consume the Rust stack, and tail
+/// calls run in constant space.
+fn run(e: Rc<Expr>, meter: &mut Meter) -> Result<Value, EvalError> {
+    let mut stack: Vec<Frame> = vec![];
+    let mut control = Control::Eval(e, None);
+
+    let mut forcing: Vec<Forcing> = vec![];
+    // Orders the creation of thunks and the start of forces.
+    let mut clock = 0;
+    let mut tick = || {
+        clock += 1;
+        clock
+    };
+
+    loop {
+        meter.stack_depth(stack.len())?;
+        let value = match control {
+            Control::Return(v) => v,
+            Control::Eval(e, env) => match e.as_ref() {
+                Expr::Bool(_) | Expr::Int(_) | Expr::String(_) => Value::Lit(e.as_ref().clone()),
+                Expr::Var(v) => {
+                    let thunk = lookup(&env, *v)
+                        .ok_or(EvalError::UnboundVariable(*v))?
+                        .clone();
+                    let state = thunk.state.borrow().clone();
+                    match state {
+                        ThunkState::Forced(v, cost) => {
+                            if let Some(cost) = cost {
+                                meter.charge(&cost)?;
+                            }
+                            v
+                        }
+                        ThunkState::Delayed(e, env) => {
+                            forcing.push(Forcing {
+                                start: meter.counters.clone(),
+                                entered: tick(),
+                                shared: thunk.shared.then_some(thunk.created),
+                                excluded: Counters::default(),
+                            });
+                            meter.depth(forcing.len());
+                            stack.push(Frame::Update(thunk));
+                            control = Control::Eval(e, env);
+                            continue;
+                        }
+                    }
+                }
+                Expr::Lambda(v, body) => Value::Closure(*v, body.clone(), env),
+                Expr::Un(op, e) => {
+                    stack.push(Frame::Un(*op));
+                    control = Control::Eval(e.clone(), env);
+                    continue;
+                }
+                Expr::Bin(op @ (BinOp::App | BinOp::AppL | BinOp::AppV), l, r) => {
+                    stack.push(Frame::Apply(*op, r.clone(), env.clone()));
+                    control = Control::Eval(l.clone(), env);
+                    continue;
+                }
+                Expr::Bin(op, l, r) => {
+                    stack.push(Frame::BinLeft(*op, r.clone(), env.clone()));
+                    control = Control::Eval(l.clone(), env);
+                    continue;
+                }
+                Expr::If(cond, th, el) => {
+                    stack.push(Frame::If(th.clone(), el.clone(), env.clone()));
+                    control = Control::Eval(cond.clone(), env);
+                    continue;
+                }
+            },
+        };
+
+        let Some(frame) = stack.pop() else {
+            return Ok(value);
+        };
+        control = match frame {
+            Frame::Apply(op, r, env) => {
+                let (v, body, cenv) = match value {
+                    Value::Closure(v, body, cenv) => (v, body, cenv),
+                    Value::Lit(f) => return Err(type_mismatch(Operator::Bin(op), &[&f])),
+                };
+                let arg = match op {
+                    BinOp::App => Thunk::delayed(r, env, false, tick()),
+                    BinOp::AppL => Thunk::delayed(r, env, true, tick()),
+                    _ => {
+                        stack.push(Frame::Call(v, body, cenv));
+                        control = Control::Eval(r, env);
+                        continue;
+                    }
+                };
+                meter.beta(op)?;
+                Control::Eval(body, bind(&cenv, v, arg))
+            }
+            Frame::Call(v, body, cenv) => {
+                meter.beta(BinOp::AppV)?;
+                Control::Eval(body, bind(&cenv, v, Thunk::forced(value)))
+            }
+            Frame::Un(op) => {
+                meter.op(Operator::Un(op));
+                let ret = apply_un(op, &lit(value))?;
+                meter.check_lit(&ret)?;
+                Control::Return(Value::Lit(ret))
+            }
+            Frame::BinLeft(op, r, env) => {
+                stack.push(Frame::BinRight(op, lit(value)));
+                Control::Eval(r, env)
+            }
+            Frame::BinRight(op, l) => {
+                meter.op(Operator::Bin(op));
+                let ret = apply_bin(op, &l, &lit(value))?;
+                meter.check_lit(&ret)?;
+                Control::Return(Value::Lit(ret))
+            }
+            Frame::If(th, el, env) => {
+                meter.op(Operator::If);
+                match lit(value) {
+                    Expr::Bool(true) => Control::Eval(th, env),
+                    Expr::Bool(false) => Control::Eval(el, env),
+                    cond => return Err(type_mismatch(Operator::If, &[&cond])),
+                }
+            }
+            Frame::Update(thunk) => {
+                let f = forcing.pop().unwrap();
+                let work = meter.counters.since(&f.start);
+                let cost = match f.shared {
+                    Some(created) => {
+                        exclude(&mut forcing, created, &work);
+                        None
+                    }
+                    None => {
+                        let cost = work.since(&f.excluded);
+                        (!cost.is_zero()).then(|| Rc::new(cost))
+                    }
+                };
+                *thunk.state.borrow_mut() = ThunkState::Forced(value.clone(), cost);
+                Control::Return(value)
+            }
+        };
+    }
+}
+
+/// Excludes the `work` of the first force of a shared thunk created at
+/// `created` from the costs of the enclosing call-by-name thunks entered after
+/// it. Thunks inside the force of an older shared thunk already exclude it
+/// along with the older one.
+fn exclude(forcing: &mut [Forcing], created: u64, work: &Counters) {
+    let mut oldest = u64::MAX;
+    for f in forcing.iter_mut().rev() {
+        if f.entered < created {
+            break;
+        }
+        match f.shared {
+            Some(created) => oldest = oldest.min(created),
+            None if f.entered < oldest => f.excluded.add(work),
+            None => {}
+        }
+    }
+}
+
+fn lit(v: Value) -> Expr {
+    match v {
+        Value::Lit(e) => e,
+        v => read_back(&v),
+    }
+}
+
+/// Converts a value back into a closed expression by substituting the
+/// captured environment into closure bodies.
+fn read_back(v: &Value) -> Expr {
+    match v {
+        Value::Lit(e) => e.clone(),
+        Value::Closure(v, body, env) => {
+            Expr::Lambda(*v, read_back_expr(body, env, &mut vec![*v]).into())
+        }
+    }
+}
+
+fn read_back_expr(e: &Expr, env: &Env, bound: &mut Vec<usize>) -> Expr {
+    match e {
+        Expr::Var(v) if !bound.contains(v) => match lookup(env, *v) {
+            Some(thunk) => match &*thunk.state.borrow() {
+                ThunkState::Forced(v, _) => read_back(v),
+                ThunkState::Delayed(e, env) => read_back_expr(e, env, &mut vec![]),
+            },
+            None => e.clone(),
+        },
+        Expr::Un(op, e) => Expr::Un(*op, read_back_expr(e, env, bound).into()),
+        Expr::Bin(op, l, r) => Expr::Bin(
+            *op,
+            read_back_expr(l, env, bound).into(),
+            read_back_expr(r, env, bound).into(),
+        ),
+        Expr::If(cond, th, el) => Expr::If(
+            read_back_expr(cond, env, bound).into(),
+            read_back_expr(th, env, bound).into(),
+            read_back_expr(el, env, bound).into(),
+        ),
+        Expr::Lambda(v, e) => {
+            bound.push(*v);
+            let e = read_back_expr(e, env, bound);
+            bound.pop();
+            Expr::Lambda(*v, e.into())
+        }
+        _ => e.clone(),
+    }
+}
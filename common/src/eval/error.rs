@@ -0,0 +1,168 @@
+use std::time::Duration;
+
+use num_bigint::BigInt;
+
+use crate::expr::{BinOp, Expr, UnOp};
+
+/// The kind of a value an operator was applied to.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Kind {
+    Bool,
+    Int,
+    String,
+    Lambda,
+    /// Not a value. Only seen when an open term gets stuck.
+    Term,
+}
+
+impl Kind {
+    pub fn of(e: &Expr) -> Kind {
+        match e {
+            Expr::Bool(_) => Kind::Bool,
+            Expr::Int(_) => Kind::Int,
+            Expr::String(_) => Kind::String,
+            Expr::Lambda(_, _) => Kind::Lambda,
+            _ => Kind::Term,
+        }
+    }
+}
+
+impl std::fmt::Display for Kind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = match self {
+            Kind::Bool => "bool",
+            Kind::Int => "int",
+            Kind::String => "string",
+            Kind::Lambda => "lambda",
+            Kind::Term => "term",
+        };
+        write!(f, "{kind}")
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+pub enum Operator {
+    Un(UnOp),
+    Bin(BinOp),
+    If,
+}
+
+pub(super) const NUM_OPERATORS: usize = UnOp::ALL.len() + BinOp::ALL.len() + 1;
+
+impl Operator {
+    pub(super) fn index(self) -> usize {
+        match self {
+            Operator::Un(op) => op as usize,
+            Operator::Bin(op) => UnOp::ALL.len() + op as usize,
+            Operator::If => NUM_OPERATORS - 1,
+        }
+    }
+
+    pub fn all() -> impl Iterator<Item = Operator> {
+        UnOp::ALL
+            .into_iter()
+            .map(Operator::Un)
+            .chain(BinOp::ALL.into_iter().map(Operator::Bin))
+            .chain([Operator::If])
+    }
+}
+
+impl std::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operator::Un(op) => write!(f, "{op}"),
+            Operator::Bin(op) => write!(f, "{op}"),
+            Operator::If => write!(f, "if"),
+        }
+    }
+}
+
+/// A limit from `EvalOptions`, with its configured value.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Limit {
+    BetaReductions(usize),
+    Time(Duration),
+    TermSize(usize),
+    StackDepth(usize),
+    IntBits(u64),
+}
+
+impl std::fmt::Display for Limit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::BetaReductions(n) => write!(f, "{n} beta reductions"),
+            Limit::Time(d) => write!(f, "{d:?} of wall time"),
+            Limit::TermSize(n) => write!(f, "term size of {n}"),
+            Limit::StackDepth(n) => write!(f, "stack depth of {n}"),
+            Limit::IntBits(n) => write!(f, "{n}-bit integers"),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum EvalError {
+    /// An operator was applied to operands of the wrong kinds. For
+    /// applications, only the kind of the function is reported.
+    TypeMismatch {
+        op: Operator,
+        operands: Vec<Kind>,
+    },
+    DivisionByZero {
+        op: BinOp,
+    },
+    /// A negative length for take or drop, or a negative integer converted to
+    /// a string.
+    OutOfRange {
+        op: Operator,
+        value: BigInt,
+    },
+    /// A string containing a character outside the ICFP character set was
+    /// converted to an integer.
+    UnencodableChar(char),
+    UnboundVariable(usize),
+    LimitExceeded {
+        limit: Limit,
+        beta_reductions: usize,
+        elapsed: Duration,
+    },
+}
+
+impl std::fmt::Display for EvalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EvalError::TypeMismatch { op, operands } => {
+                write!(f, "type mismatch: `{op}` applied to ")?;
+                for (i, kind) in operands.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{kind}")?;
+                }
+                Ok(())
+            }
+            EvalError::DivisionByZero { op } => write!(f, "division by zero: `{op}`"),
+            EvalError::OutOfRange { op, value } => {
+                write!(f, "operand out of range: `{op}` applied to {value}")
+            }
+            EvalError::UnencodableChar(c) => write!(f, "unencodable character: {c:?}"),
+            EvalError::UnboundVariable(v) => write!(f, "unbound variable: v{v}"),
+            EvalError::LimitExceeded {
+                limit,
+                beta_reductions,
+                elapsed,
+            } => write!(
+                f,
+                "exceeded the limit of {limit} after {beta_reductions} beta reductions in {elapsed:?}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+pub(super) fn type_mismatch(op: Operator, operands: &[&Expr]) -> EvalError {
+    EvalError::TypeMismatch {
+        op,
+        operands: operands.iter().map(|e| Kind::of(e)).collect(),
+    }
+}
@@ -0,0 +1,1069 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use num_bigint::{BigInt, Sign};
+
+use crate::{
+    base94::{char_index, ALPHABET},
+    expr::{BinOp, Expr, UnOp},
+    renumber::renumber_vars,
+};
+
+mod env;
+mod error;
+mod normalize;
+mod step;
+mod subst;
+
+use error::{type_mismatch, NUM_OPERATORS};
+pub use error::{EvalError, Kind, Limit, Operator};
+pub use step::{Rule, Step, Stepper};
+use subst::max_var;
+pub use subst::{alpha_eq, substitute};
+
+/// Statistics of an evaluation, returned by `eval_with_stats`.
+#[derive(PartialEq, Eq, Default, Clone, Debug)]
+pub struct Stats {
+    pub beta_reductions: usize,
+    /// How many times each operator was applied. Operators never applied are
+    /// omitted.
+    pub op_counts: BTreeMap<Operator, usize>,
+    /// The largest term size seen, measured as for
+    /// `EvalOptions::max_term_size`.
+    pub peak_term_size: usize,
+    /// The deepest continuation stack of `Strategy::Environment`, measured as
+    /// for `EvalOptions::max_stack_depth`.
+    pub peak_stack_depth: usize,
+    /// The deepest nesting of evaluations: the recursion depth with
+    /// `Strategy::Substitution`, and the number of nested argument forces with
+    /// `Strategy::Environment`.
+    pub max_depth: usize,
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "beta reductions: {}", self.beta_reductions)?;
+        writeln!(f, "peak term size: {}", self.peak_term_size)?;
+        writeln!(f, "peak stack depth: {}", self.peak_stack_depth)?;
+        writeln!(f, "max depth: {}", self.max_depth)?;
+        write!(f, "operators:")?;
+        for (op, n) in &self.op_counts {
+            write!(f, " {op}:{n}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Counts of work done, which can be recorded for a call-by-name argument and
+/// charged again each time the argument is used.
+#[derive(Default, Clone, Debug)]
+struct Counters {
+    beta_reductions: usize,
+    ops: [usize; NUM_OPERATORS],
+}
+
+impl Counters {
+    fn since(&self, start: &Counters) -> Counters {
+        let mut ret = self.clone();
+        ret.beta_reductions -= start.beta_reductions;
+        for (n, m) in ret.ops.iter_mut().zip(start.ops) {
+            *n -= m;
+        }
+        ret
+    }
+
+    fn add(&mut self, other: &Counters) {
+        self.beta_reductions += other.beta_reductions;
+        for (n, m) in self.ops.iter_mut().zip(other.ops) {
+            *n += m;
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.beta_reductions == 0 && self.ops.iter().all(|n| *n == 0)
+    }
+}
+
+/// How `B$`/`B~`/`B!` applications are carried out. Both strategies produce
+/// the same results and beta reduction counts.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Strategy {
+    /// Rewrites lambda bodies by substituting arguments. Simple but slow, and
+    /// recurses on the Rust stack.
+    Substitution,
+    /// Binds arguments in environments and evaluates lambdas to closures with
+    /// an explicit-stack machine.
+    #[default]
+    Environment,
+}
+
+/// Options for `eval_with`. Limits left as `None` are not enforced.
+#[derive(Clone, Debug, Default)]
+pub struct EvalOptions {
+    pub strategy: Strategy,
+    pub max_beta_reductions: Option<usize>,
+    pub timeout: Option<Duration>,
+    /// Bounds the number of nodes of the term being reduced with
+    /// `Strategy::Substitution` or by `normalize`.
+    pub max_term_size: Option<usize>,
+    /// Bounds the number of frames of the continuation stack with
+    /// `Strategy::Environment`.
+    pub max_stack_depth: Option<usize>,
+    /// Bounds the number of bits of integers computed by operators.
+    pub max_int_bits: Option<u64>,
+    /// Also reduces under lambdas, so that a function is returned in its
+    /// normal form. This is done by substitution after the evaluation, with
+    /// the same limits.
+    pub normalize: bool,
+}
+
+/// Keeps statistics of an evaluation and enforces the limits in
+/// `EvalOptions`.
+struct Meter<'a> {
+    counters: Counters,
+    peak_term_size: usize,
+    peak_stack_depth: usize,
+    max_depth: usize,
+    options: &'a EvalOptions,
+    start: Instant,
+}
+
+impl<'a> Meter<'a> {
+    fn new(options: &'a EvalOptions) -> Self {
+        Meter {
+            counters: Counters::default(),
+            peak_term_size: 0,
+            peak_stack_depth: 0,
+            max_depth: 0,
+            options,
+            start: Instant::now(),
+        }
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            beta_reductions: self.counters.beta_reductions,
+            op_counts: Operator::all()
+                .map(|op| (op, self.counters.ops[op.index()]))
+                .filter(|(_, n)| *n > 0)
+                .collect(),
+            peak_term_size: self.peak_term_size,
+            peak_stack_depth: self.peak_stack_depth,
+            max_depth: self.max_depth,
+        }
+    }
+
+    fn limit_exceeded(&self, limit: Limit) -> EvalError {
+        EvalError::LimitExceeded {
+            limit,
+            beta_reductions: self.counters.beta_reductions,
+            elapsed: self.start.elapsed(),
+        }
+    }
+
+    fn op(&mut self, op: Operator) {
+        self.counters.ops[op.index()] += 1;
+    }
+
+    /// Counts a beta reduction done by the application operator `op`.
+    fn beta(&mut self, op: BinOp) -> Result<(), EvalError> {
+        self.op(Operator::Bin(op));
+        self.counters.beta_reductions += 1;
+        self.check_beta_reductions(self.counters.beta_reductions - 1)
+    }
+
+    /// Counts the work recorded in `cost` once more.
+    fn charge(&mut self, cost: &Counters) -> Result<(), EvalError> {
+        let before = self.counters.beta_reductions;
+        self.counters.add(cost);
+        self.check_beta_reductions(before)
+    }
+
+    fn check_beta_reductions(&self, before: usize) -> Result<(), EvalError> {
+        let n = self.counters.beta_reductions;
+        if let Some(max) = self.options.max_beta_reductions {
+            if n > max {
+                return Err(self.limit_exceeded(Limit::BetaReductions(max)));
+            }
+        }
+        // Reading the clock is not free, so check it every 1024 reductions.
+        if let Some(timeout) = self.options.timeout {
+            if before / 1024 != n / 1024 && self.start.elapsed() > timeout {
+                return Err(self.limit_exceeded(Limit::Time(timeout)));
+            }
+        }
+        Ok(())
+    }
+
+    fn term_size(&mut self, size: usize) -> Result<(), EvalError> {
+        self.peak_term_size = self.peak_term_size.max(size);
+        match self.options.max_term_size {
+            Some(max) if size > max => Err(self.limit_exceeded(Limit::TermSize(max))),
+            _ => Ok(()),
+        }
+    }
+
+    fn stack_depth(&mut self, depth: usize) -> Result<(), EvalError> {
+        self.peak_stack_depth = self.peak_stack_depth.max(depth);
+        match self.options.max_stack_depth {
+            Some(max) if depth > max => Err(self.limit_exceeded(Limit::StackDepth(max))),
+            _ => Ok(()),
+        }
+    }
+
+    fn depth(&mut self, depth: usize) {
+        self.max_depth = self.max_depth.max(depth);
+    }
+
+    /// Checks a value computed by an operator.
+    fn check_lit(&self, e: &Expr) -> Result<(), EvalError> {
+        match (e, self.options.max_int_bits) {
+            (Expr::Int(n), Some(max)) if n.bits() > max => {
+                Err(self.limit_exceeded(Limit::IntBits(max)))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A shared argument of a call-by-need application (`B~`). It is forced at
+/// most once; later references reuse the value.
+#[derive(Clone, Debug)]
+enum Thunk {
+    Delayed(Expr),
+    Forced(Expr),
+}
+
+/// Thunks are substituted into lambda bodies as variables with indices at or
+/// above this value, so that substitution keeps working on plain `Expr`s.
+/// Programs using such huge variable numbers are renumbered by
+/// `below_thunk_vars` before they are evaluated.
+const THUNK_VAR_BASE: usize = usize::MAX / 2;
+
+struct Context<'a> {
+    meter: Meter<'a>,
+    thunks: Vec<Thunk>,
+    depth: usize,
+}
+
+impl<'a> Context<'a> {
+    fn new(options: &'a EvalOptions) -> Self {
+        Context {
+            meter: Meter::new(options),
+            thunks: vec![],
+            depth: 0,
+        }
+    }
+
+    fn new_thunk(&mut self, e: &Expr) -> Expr {
+        // Values are cheap to copy, so there is nothing to share.
+        if e.is_nf() {
+            return e.clone();
+        }
+        self.thunks.push(Thunk::Delayed(e.clone()));
+        Expr::Var(THUNK_VAR_BASE + self.thunks.len() - 1)
+    }
+
+    fn force(&mut self, ix: usize) -> Result<Expr, EvalError> {
+        let e = match &self.thunks[ix] {
+            Thunk::Forced(v) => return Ok(v.clone()),
+            Thunk::Delayed(e) => e.clone(),
+        };
+        log::trace!("force: {e}");
+        let v = reduce_to_nf(&e, self)?;
+        self.thunks[ix] = Thunk::Forced(v.clone());
+        Ok(v)
+    }
+
+    /// Replaces the thunk variables remaining in `e` (e.g. inside a returned
+    /// lambda) with the terms they stand for.
+    fn unshare(&self, e: &Expr) -> Expr {
+        unshare(e, &|i| match &self.thunks[i] {
+            Thunk::Delayed(e) | Thunk::Forced(e) => Some(e),
+        })
+    }
+
+    /// Counts a beta reduction by `op` that produced `e`.
+    fn beta(&mut self, op: BinOp, e: &Expr) -> Result<(), EvalError> {
+        self.meter.beta(op)?;
+        self.meter.term_size(e.num_nodes())
+    }
+}
+
+/// Replaces the thunk variables in `e` with the terms that `thunk` returns
+/// for their indices, recursively. Variables without a term are kept.
+fn unshare<'a>(e: &Expr, thunk: &impl Fn(usize) -> Option<&'a Expr>) -> Expr {
+    match e {
+        Expr::Var(v) if *v >= THUNK_VAR_BASE => match thunk(*v - THUNK_VAR_BASE) {
+            Some(e) => unshare(e, thunk),
+            None => e.clone(),
+        },
+        Expr::Un(op, e) => Expr::Un(*op, unshare(e, thunk).into()),
+        Expr::Bin(op, l, r) => Expr::Bin(*op, unshare(l, thunk).into(), unshare(r, thunk).into()),
+        Expr::If(cond, th, el) => Expr::If(
+            unshare(cond, thunk).into(),
+            unshare(th, thunk).into(),
+            unshare(el, thunk).into(),
+        ),
+        Expr::Lambda(v, e) => Expr::Lambda(*v, unshare(e, thunk).into()),
+        _ => e.clone(),
+    }
+}
+
+/// Renumbers the bound variables of `e` if any of them could be mistaken for
+/// a thunk variable. Free variables are kept.
+fn below_thunk_vars(e: &Expr) -> Cow<'_, Expr> {
+    if max_var(e) < THUNK_VAR_BASE {
+        return Cow::Borrowed(e);
+    }
+    // Renumbering shortens any program with a variable that large, so it
+    // never returns the program as is.
+    Cow::Owned(renumber_vars(e).expr)
+}
+
+pub fn eval(e: &Expr) -> anyhow::Result<Expr> {
+    Ok(eval_with(e, &EvalOptions::default())?)
+}
+
+pub fn eval_with(e: &Expr, options: &EvalOptions) -> Result<Expr, EvalError> {
+    Ok(eval_with_stats(e, options)?.0)
+}
+
+pub fn eval_with_stats(e: &Expr, options: &EvalOptions) -> Result<(Expr, Stats), EvalError> {
+    // An open term would fail only when the evaluation reaches the variable,
+    // possibly after a long time.
+    if let Some(&v) = e.free_vars().first() {
+        return Err(EvalError::UnboundVariable(v));
+    }
+    let (ret, mut meter) = match options.strategy {
+        Strategy::Substitution => {
+            // The program is closed, so this renames every variable out of
+            // the way of the thunk variables.
+            let e = below_thunk_vars(e);
+            let mut ctx = Context::new(options);
+            let ret = reduce_to_nf(&e, &mut ctx)?;
+            (ctx.unshare(&ret), ctx.meter)
+        }
+        Strategy::Environment => {
+            let mut meter = Meter::new(options);
+            let ret = env::eval(e, &mut meter)?;
+            (ret, meter)
+        }
+    };
+    let ret = if options.normalize {
+        normalize::normalize(&ret, &mut meter)?
+    } else {
+        ret
+    };
+    Ok((ret, meter.stats()))
+}
+
+fn reduce_to_nf(e: &Expr, ctx: &mut Context) -> Result<Expr, EvalError> {
+    ctx.depth += 1;
+    ctx.meter.depth(ctx.depth);
+    let ret = reduce(e, ctx);
+    ctx.depth -= 1;
+    ret
+}
+
+fn reduce(e: &Expr, ctx: &mut Context) -> Result<Expr, EvalError> {
+    log::trace!("eval: {e}");
+
+    Ok(match e {
+        Expr::Var(v) if *v >= THUNK_VAR_BASE => ctx.force(*v - THUNK_VAR_BASE)?,
+        Expr::Var(v) => return Err(EvalError::UnboundVariable(*v)),
+        Expr::Un(op, e) => {
+            let e = reduce_to_nf(e.as_ref(), ctx)?;
+            ctx.meter.op(Operator::Un(*op));
+            let ret = apply_un(*op, &e)?;
+            ctx.meter.check_lit(&ret)?;
+            ret
+        }
+        Expr::Bin(op @ (BinOp::App | BinOp::AppL | BinOp::AppV), l, r) => {
+            log::trace!("app: {l}, {r}");
+            let f = reduce_to_nf(l.as_ref(), ctx)?;
+            let Expr::Lambda(v, e) = f else {
+                return Err(type_mismatch(Operator::Bin(*op), &[&f]));
+            };
+            let arg = match op {
+                BinOp::App => r.as_ref().clone(),
+                BinOp::AppL => ctx.new_thunk(r.as_ref()),
+                // It's okay to eval the rhs because it's call-by-value.
+                _ => reduce_to_nf(r.as_ref(), ctx)?,
+            };
+            let e = substitute(e.as_ref(), v, &arg);
+            ctx.beta(*op, &e)?;
+            reduce_to_nf(&e, ctx)?
+        }
+        Expr::Bin(op, l, r) => {
+            let l = reduce_to_nf(l.as_ref(), ctx)?;
+            let r = reduce_to_nf(r.as_ref(), ctx)?;
+            ctx.meter.op(Operator::Bin(*op));
+            let ret = apply_bin(*op, &l, &r)?;
+            ctx.meter.check_lit(&ret)?;
+            ret
+        }
+        Expr::If(cond, th, el) => {
+            let cond = reduce_to_nf(cond.as_ref(), ctx)?;
+            ctx.meter.op(Operator::If);
+            match cond {
+                Expr::Bool(true) => reduce_to_nf(th.as_ref(), ctx)?,
+                Expr::Bool(false) => reduce_to_nf(el.as_ref(), ctx)?,
+                _ => return Err(type_mismatch(Operator::If, &[&cond])),
+            }
+        }
+        _ => e.clone(),
+    })
+}
+
+pub(crate) fn apply_un(op: UnOp, e: &Expr) -> Result<Expr, EvalError> {
+    Ok(match (op, e) {
+        (UnOp::Neg, Expr::Int(n)) => Expr::Int(Rc::new(-n.as_ref().clone())),
+        (UnOp::Not, Expr::Bool(b)) => Expr::Bool(!b),
+        (UnOp::StrToInt, Expr::String(s)) => Expr::Int(str_to_int(s)?.into()),
+        (UnOp::IntToStr, Expr::Int(n)) => Expr::String(int_to_str(n)?.into()),
+        _ => return Err(type_mismatch(Operator::Un(op), &[e])),
+    })
+}
+
+pub(crate) fn apply_bin(op: BinOp, l: &Expr, r: &Expr) -> Result<Expr, EvalError> {
+    Ok(match (op, l, r) {
+        (BinOp::Add, Expr::Int(n1), Expr::Int(n2)) => Expr::Int((n1.as_ref() + n2.as_ref()).into()),
+        (BinOp::Sub, Expr::Int(n1), Expr::Int(n2)) => Expr::Int((n1.as_ref() - n2.as_ref()).into()),
+        (BinOp::Mul, Expr::Int(n1), Expr::Int(n2)) => Expr::Int((n1.as_ref() * n2.as_ref()).into()),
+        (BinOp::Div | BinOp::Mod, Expr::Int(_), Expr::Int(n2)) if n2.as_ref() == &BigInt::ZERO => {
+            return Err(EvalError::DivisionByZero { op })
+        }
+        (BinOp::Div, Expr::Int(n1), Expr::Int(n2)) => Expr::Int((n1.as_ref() / n2.as_ref()).into()),
+        (BinOp::Mod, Expr::Int(n1), Expr::Int(n2)) => Expr::Int((n1.as_ref() % n2.as_ref()).into()),
+        (BinOp::Lt, Expr::Int(n1), Expr::Int(n2)) => Expr::Bool(n1 < n2),
+        (BinOp::Gt, Expr::Int(n1), Expr::Int(n2)) => Expr::Bool(n1 > n2),
+        (BinOp::Eq, Expr::Int(n1), Expr::Int(n2)) => Expr::Bool(n1 == n2),
+        (BinOp::Eq, Expr::String(n1), Expr::String(n2)) => Expr::Bool(n1 == n2),
+        (BinOp::Eq, Expr::Bool(n1), Expr::Bool(n2)) => Expr::Bool(n1 == n2),
+        (BinOp::Or, Expr::Bool(b1), Expr::Bool(b2)) => Expr::Bool(*b1 || *b2),
+        (BinOp::And, Expr::Bool(b1), Expr::Bool(b2)) => Expr::Bool(*b1 && *b2),
+        (BinOp::Concat, Expr::String(s1), Expr::String(s2)) => {
+            Expr::String((s1.as_ref().clone() + s2.as_ref()).into())
+        }
+        (BinOp::Take, Expr::Int(n), Expr::String(s)) => {
+            Expr::String(s.chars().take(length(op, n)?).collect::<String>().into())
+        }
+        (BinOp::Drop, Expr::Int(n), Expr::String(s)) => {
+            Expr::String(s.chars().skip(length(op, n)?).collect::<String>().into())
+        }
+        _ => return Err(type_mismatch(Operator::Bin(op), &[l, r])),
+    })
+}
+
+/// Reads the string as a base-94 number, whose digits are the positions of
+/// the characters in the ICFP character set.
+fn str_to_int(s: &str) -> Result<BigInt, EvalError> {
+    let digits = s
+        .chars()
+        .map(|c| {
+            char_index(c)
+                .map(|d| d as u8)
+                .ok_or(EvalError::UnencodableChar(c))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(BigInt::from_radix_be(Sign::Plus, &digits, 94).unwrap())
+}
+
+/// The inverse of `str_to_int`. Zero is the single-digit string "a".
+fn int_to_str(n: &BigInt) -> Result<String, EvalError> {
+    if n.sign() == Sign::Minus {
+        return Err(EvalError::OutOfRange {
+            op: Operator::Un(UnOp::IntToStr),
+            value: n.clone(),
+        });
+    }
+    let (_, digits) = n.to_radix_be(94);
+    Ok(digits
+        .into_iter()
+        .map(|d| ALPHABET[d as usize] as char)
+        .collect())
+}
+
+/// Converts the length operand of take and drop. Lengths beyond `usize` are
+/// longer than any string, so they are clamped.
+fn length(op: BinOp, n: &BigInt) -> Result<usize, EvalError> {
+    if n.sign() == Sign::Minus {
+        return Err(EvalError::OutOfRange {
+            op: Operator::Bin(op),
+            value: n.clone(),
+        });
+    }
+    Ok(n.try_into().unwrap_or(usize::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    const LANGUAGE_TEST: &str = r#"? B= B$ B$ B$ B$ L$ L$ L$ L# v$ I" I# I$ I% I$ ? B= B$ L$ v$ I+ I+ ? B= BD I$ S4%34 S4 ? B= BT I$ S4%34 S4%3 ? B= B. S4% S34 S4%34 ? U! B& T F ? B& T T ? U! B| F F ? B| F T ? B< U- I$ U- I# ? B> I$ I# ? B= U- I" B% U- I$ I# ? B= I" B% I( I$ ? B= U- I" B/ U- I$ I# ? B= I# B/ I( I$ ? B= I' B* I# I$ ? B= I$ B+ I" I# ? B= U$ I4%34 S4%34 ? B= U# S4%34 I4%34 ? U! F ? B= U- I$ B- I# I& ? B= I$ B- I& I# ? B= S4%34 S4%34 ? B= F F ? B= I$ I$ ? T B. B. SM%,&k#(%#+}IEj}3%.$}z3/,6%},!.'5!'%y4%34} U$ B+ I# B* I$> I1~s:U@ Sz}4/}#,!)-}0/).43}&/2})4 S)&})3}./4}#/22%#4 S").!29}q})3}./4}#/22%#4 S").!29}q})3}./4}#/22%#4 S").!29}q})3}./4}#/22%#4 S").!29}k})3}./4}#/22%#4 S5.!29}k})3}./4}#/22%#4 S5.!29}_})3}./4}#/22%#4 S5.!29}a})3}./4}#/22%#4 S5.!29}b})3}./4}#/22%#4 S").!29}i})3}./4}#/22%#4 S").!29}h})3}./4}#/22%#4 S").!29}m})3}./4}#/22%#4 S").!29}m})3}./4}#/22%#4 S").!29}c})3}./4}#/22%#4 S").!29}c})3}./4}#/22%#4 S").!29}r})3}./4}#/22%#4 S").!29}p})3}./4}#/22%#4 S").!29}{})3}./4}#/22%#4 S").!29}{})3}./4}#/22%#4 S").!29}d})3}./4}#/22%#4 S").!29}d})3}./4}#/22%#4 S").!29}l})3}./4}#/22%#4 S").!29}N})3}./4}#/22%#4 S").!29}>})3}./4}#/22%#4 S!00,)#!4)/.})3}./4}#/22%#4 S!00,)#!4)/.})3}./4}#/22%#4"#;
+
+    const BETA_REDUCTIONS: &str = r#"B$ B$ L" B$ L# B$ v" B$ v# v# L# B$ v" B$ v# v# L" L# ? B= v# I! I" B$ L$ B+ B$ v" v$ B$ v" v$ B- v# I" I%"#;
+
+    #[test]
+    fn conversion() {
+        assert_eq!(str_to_int("test").unwrap(), 15818151.into());
+        assert_eq!(int_to_str(&15818151.into()).unwrap(), "test");
+    }
+
+    #[test]
+    fn language_test() {
+        let expr: Expr = LANGUAGE_TEST.parse().unwrap();
+        assert_eq!(
+            eval(&expr).unwrap(),
+            Expr::String(
+                "Self-check OK, send `solve language_test 4w3s0m3` to claim points for it"
+                    .to_string()
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn large_variables() {
+        // The largest variables are at least `THUNK_VAR_BASE`.
+        let v = crate::base94::encode_base94_int(&THUNK_VAR_BASE.into()).unwrap();
+        let w = crate::base94::encode_base94_int(&usize::MAX.into()).unwrap();
+        for strategy in [Strategy::Substitution, Strategy::Environment] {
+            let options = EvalOptions {
+                strategy,
+                ..Default::default()
+            };
+            let id: Expr = format!("L{v} v{v}").parse().unwrap();
+            let ret = eval_with(&id, &options).unwrap();
+            assert!(alpha_eq(&ret, &id), "{ret}");
+            let e: Expr = format!("B~ L{v} B~ L{w} B+ v{v} v{w} B+ I# I# I$")
+                .parse()
+                .unwrap();
+            let (ret, stats) = eval_with_stats(&e, &options).unwrap();
+            assert_eq!(ret, Expr::Int(BigInt::from(7).into()));
+            assert_eq!(stats.beta_reductions, 2);
+        }
+    }
+
+    #[test]
+    fn beta_reductions() {
+        let expr: Expr = BETA_REDUCTIONS.parse().unwrap();
+        for strategy in [Strategy::Substitution, Strategy::Environment] {
+            let options = EvalOptions {
+                strategy,
+                ..Default::default()
+            };
+            let (e, stats) = eval_with_stats(&expr, &options).unwrap();
+            assert_eq!(e, Expr::Int(BigInt::from(16).into()));
+            assert_eq!(stats.beta_reductions, 109);
+        }
+    }
+
+    #[test]
+    fn call_by_need() {
+        // ((λv2. v2 + v2) ((λv3. v3) 1)): the argument is reduced only once.
+        let expr: Expr = r#"B~ L# B+ v# v# B$ L$ v$ I""#.parse().unwrap();
+        let (e, stats) = eval_with_stats(&expr, &EvalOptions::default()).unwrap();
+        assert_eq!(e, Expr::Int(BigInt::from(2).into()));
+        assert_eq!(stats.beta_reductions, 2);
+
+        // The same program with B$ reduces the argument at each use.
+        let expr: Expr = r#"B$ L# B+ v# v# B$ L$ v$ I""#.parse().unwrap();
+        let (_, stats) = eval_with_stats(&expr, &EvalOptions::default()).unwrap();
+        assert_eq!(stats.beta_reductions, 3);
+
+        // Unused arguments are never evaluated.
+        let expr: Expr = r#"B~ L# I" U- T"#.parse().unwrap();
+        assert_eq!(eval(&expr).unwrap(), Expr::Int(BigInt::from(1).into()));
+
+        // Shared arguments captured by a returned lambda are expanded back.
+        let expr: Expr = r#"B~ L# L$ v# B+ I" I""#.parse().unwrap();
+        assert_eq!(eval(&expr).unwrap(), r#"L$ B+ I" I""#.parse().unwrap());
+    }
+
+    #[test]
+    fn normalize() {
+        for strategy in [Strategy::Substitution, Strategy::Environment] {
+            let options = EvalOptions {
+                strategy,
+                normalize: true,
+                ..Default::default()
+            };
+            for (program, expected) in [
+                // λv2. (λv3. v3 + 1) 2 => λv2. 3
+                (r#"L# B$ L$ B+ v$ I" I#"#, r#"L# I$"#),
+                // Operators on free variables are left as is.
+                (r#"L# B+ v# B* I# I$"#, r#"L# B+ v# I'"#),
+                // So is the division by zero in the unreachable branch.
+                (r#"L# ? v# I" B/ I" I!"#, r#"L# ? v# I" B/ I" I!"#),
+                // λv1. (λv2. λv1. v2) v1 => λv1. λv3. v1, not λv1. λv1. v1.
+                (r#"L" B$ L# L" v# v""#, r#"L" L$ v""#),
+                // Partial applications are reduced after the evaluation.
+                (r#"B$ L# L$ B+ v# v$ B+ I" I""#, r#"L$ B+ I# v$"#),
+                (BETA_REDUCTIONS, r#"I1"#),
+            ] {
+                let expr: Expr = program.parse().unwrap();
+                let expected: Expr = expected.parse().unwrap();
+                assert_eq!(eval_with(&expr, &options).unwrap(), expected, "{program}");
+            }
+
+            // The fixed-point combinator has no normal form.
+            let expr: Expr = r#"L" B$ L# B$ v" B$ v# v# L# B$ v" B$ v# v#"#.parse().unwrap();
+            let options = EvalOptions {
+                max_beta_reductions: Some(100),
+                ..options
+            };
+            assert!(matches!(
+                eval_with(&expr, &options),
+                Err(EvalError::LimitExceeded {
+                    limit: Limit::BetaReductions(100),
+                    ..
+                })
+            ));
+        }
+    }
+
+    fn assert_strategies_agree(expr: &Expr) {
+        let (expected, expected_stats) = eval_with_stats(
+            expr,
+            &EvalOptions {
+                strategy: Strategy::Substitution,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let (actual, actual_stats) = eval_with_stats(
+            expr,
+            &EvalOptions {
+                strategy: Strategy::Environment,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(actual, expected, "{expr}");
+        assert_eq!(
+            actual_stats.beta_reductions, expected_stats.beta_reductions,
+            "{expr}"
+        );
+        assert_eq!(actual_stats.op_counts, expected_stats.op_counts, "{expr}");
+
+        let mut stepper = Stepper::new(expr.clone());
+        let mut betas = 0;
+        while let Some(step) = stepper.step().unwrap() {
+            betas += matches!(step.rule, Rule::Beta(_)) as usize;
+        }
+        assert_eq!(stepper.expr(), expected, "{expr}");
+        assert_eq!(betas, expected_stats.beta_reductions, "{expr}");
+    }
+
+    /// Generates integer expressions whose variables are bound by `B$` and
+    /// `B~` applications, so that call-by-name arguments refer to shared ones
+    /// and the other way around.
+    fn random_program(rng: &mut StdRng, vars: &mut Vec<usize>, size: usize) -> Expr {
+        if size <= 1 {
+            return match rng.gen_range(0..3) {
+                0 if !vars.is_empty() => Expr::Var(vars[rng.gen_range(0..vars.len())]),
+                _ => Expr::Int(BigInt::from(rng.gen_range(0..3)).into()),
+            };
+        }
+        match rng.gen_range(0..4) {
+            0 => {
+                let l = rng.gen_range(1..size);
+                Expr::Bin(
+                    BinOp::Add,
+                    random_program(rng, vars, l).into(),
+                    random_program(rng, vars, size - l).into(),
+                )
+            }
+            1 if size >= 4 => {
+                let c = rng.gen_range(1..size - 2);
+                let t = rng.gen_range(1..size - 1 - c);
+                let cond = Expr::Bin(
+                    BinOp::Lt,
+                    random_program(rng, vars, c).into(),
+                    Expr::Int(BigInt::from(2).into()).into(),
+                );
+                Expr::If(
+                    cond.into(),
+                    random_program(rng, vars, t).into(),
+                    random_program(rng, vars, size - 1 - c - t).into(),
+                )
+            }
+            _ => {
+                let op = [BinOp::App, BinOp::AppL][rng.gen_range(0..2)];
+                let a = rng.gen_range(1..size);
+                let arg = random_program(rng, vars, a);
+                let v = rng.gen_range(1..5);
+                vars.push(v);
+                let body = random_program(rng, vars, size - a);
+                vars.pop();
+                Expr::Bin(op, Expr::Lambda(v, body.into()).into(), arg.into())
+            }
+        }
+    }
+
+    #[test]
+    fn environment_agrees_with_substitution() {
+        for program in [
+            LANGUAGE_TEST,
+            BETA_REDUCTIONS,
+            r#"B~ L# B+ v# v# B$ L$ v$ I""#,
+            r#"B~ L# L$ v# B+ I" I""#,
+            r#"B! L# L$ B. v# v$ S4%34"#,
+            // A call-by-name argument forcing a shared one: the shared work is
+            // not charged again when the argument is used twice.
+            r#"B~ L# B$ L" B+ v" v" B+ v# I" B$ L$ v$ I#"#,
+            r#"B~ L# B$ L" B+ B+ v" v" v" B+ v# I" B$ L$ v$ I#"#,
+            // The same with a recursive shared argument, sum of 1..10.
+            r#"B~ L" B$ L# B+ v# v# v" B$ B$ L" B$ L# B$ v" B$ v# v# L# B$ v" B$ v# v# L" L# ? B= v# I! I! B+ v# B$ v" B- v# I" I+"#,
+        ] {
+            assert_strategies_agree(&program.parse().unwrap());
+        }
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..2000 {
+            let size = rng.gen_range(1..20);
+            assert_strategies_agree(&random_program(&mut rng, &mut vec![], size));
+        }
+    }
+
+    #[test]
+    fn stats() {
+        // (λv1. v1 + v1) (2 * 3)
+        let expr: Expr = r#"B$ L" B+ v" v" B* I# I$"#.parse().unwrap();
+        for strategy in [Strategy::Substitution, Strategy::Environment] {
+            let options = EvalOptions {
+                strategy,
+                ..Default::default()
+            };
+            let (_, stats) = eval_with_stats(&expr, &options).unwrap();
+            assert_eq!(stats.beta_reductions, 1);
+            assert_eq!(
+                stats.op_counts,
+                BTreeMap::from([
+                    (Operator::Bin(BinOp::App), 1),
+                    (Operator::Bin(BinOp::Add), 1),
+                    (Operator::Bin(BinOp::Mul), 2),
+                ])
+            );
+        }
+
+        let options = EvalOptions {
+            strategy: Strategy::Substitution,
+            ..Default::default()
+        };
+        let (_, stats) = eval_with_stats(&expr, &options).unwrap();
+        // (+ (* 2 3) (* 2 3))
+        assert_eq!(stats.peak_term_size, 7);
+        assert_eq!(stats.max_depth, 4);
+        assert_eq!(stats.peak_stack_depth, 0);
+
+        let options = EvalOptions {
+            strategy: Strategy::Environment,
+            ..Default::default()
+        };
+        let (_, stats) = eval_with_stats(&expr, &options).unwrap();
+        // Adding, forcing v1, multiplying.
+        assert_eq!(stats.peak_stack_depth, 3);
+        assert_eq!(stats.peak_term_size, 0);
+    }
+
+    #[test]
+    fn errors() {
+        for strategy in [Strategy::Substitution, Strategy::Environment] {
+            let options = EvalOptions {
+                strategy,
+                ..Default::default()
+            };
+            let eval = |program: &str| eval_with(&program.parse().unwrap(), &options);
+
+            assert_eq!(
+                eval(r#"B+ I" S!"#),
+                Err(EvalError::TypeMismatch {
+                    op: Operator::Bin(BinOp::Add),
+                    operands: vec![Kind::Int, Kind::String],
+                })
+            );
+            assert_eq!(
+                eval(r#"U! B$ L! v! I""#),
+                Err(EvalError::TypeMismatch {
+                    op: Operator::Un(UnOp::Not),
+                    operands: vec![Kind::Int],
+                })
+            );
+            assert_eq!(
+                eval(r#"? L! v! I" I""#),
+                Err(EvalError::TypeMismatch {
+                    op: Operator::If,
+                    operands: vec![Kind::Lambda],
+                })
+            );
+            assert_eq!(
+                eval(r#"B$ I" I""#),
+                Err(EvalError::TypeMismatch {
+                    op: Operator::Bin(BinOp::App),
+                    operands: vec![Kind::Int],
+                })
+            );
+            assert_eq!(
+                eval(r#"B% I" I!"#),
+                Err(EvalError::DivisionByZero { op: BinOp::Mod })
+            );
+            assert_eq!(eval(r#"B$ L! v" I""#), Err(EvalError::UnboundVariable(1)));
+            // Open terms are rejected even if the variable is never reached.
+            assert_eq!(eval(r#"? T I" v""#), Err(EvalError::UnboundVariable(1)));
+
+            let expr = Expr::Un(UnOp::StrToInt, Expr::String("é".to_string().into()).into());
+            assert_eq!(
+                eval_with(&expr, &options),
+                Err(EvalError::UnencodableChar('é'))
+            );
+        }
+    }
+
+    #[test]
+    fn conformance() {
+        let int = |n: i64| Ok(Expr::Int(BigInt::from(n).into()));
+        let string = |s: &str| Ok(Expr::String(s.to_string().into()));
+        let bool = |b: bool| Ok(Expr::Bool(b));
+        let out_of_range = |op: Operator, n: i64| {
+            Err(EvalError::OutOfRange {
+                op,
+                value: BigInt::from(n),
+            })
+        };
+        // Larger than 2^64.
+        const HUGE: &str = "I~~~~~~~~~~~";
+
+        let table: Vec<(String, Result<Expr, EvalError>)> = vec![
+            (r#"U- I$"#.into(), int(-3)),
+            (r#"U- U- I$"#.into(), int(3)),
+            (r#"U! T"#.into(), bool(false)),
+            (r#"U# S4%34"#.into(), int(15818151)),
+            (r#"U# S"#.into(), int(0)),
+            (r#"U# S!"#.into(), int(0)),
+            (r#"U$ I4%34"#.into(), string("test")),
+            (r#"U$ I!"#.into(), string("a")),
+            (
+                r#"U$ U- I""#.into(),
+                out_of_range(Operator::Un(UnOp::IntToStr), -1),
+            ),
+            (r#"B+ I# I$"#.into(), int(5)),
+            (r#"B- I# I$"#.into(), int(-1)),
+            (r#"B* U- I# I$"#.into(), int(-6)),
+            // Division truncates toward zero, and the remainder has the sign
+            // of the dividend.
+            (r#"B/ U- I( I#"#.into(), int(-3)),
+            (r#"B/ I( U- I#"#.into(), int(-3)),
+            (
+                r#"B/ I" I!"#.into(),
+                Err(EvalError::DivisionByZero { op: BinOp::Div }),
+            ),
+            (r#"B% U- I( I#"#.into(), int(-1)),
+            (r#"B% I( U- I#"#.into(), int(1)),
+            (
+                r#"B% I" I!"#.into(),
+                Err(EvalError::DivisionByZero { op: BinOp::Mod }),
+            ),
+            (r#"B< I" I#"#.into(), bool(true)),
+            (r#"B< I# I#"#.into(), bool(false)),
+            (r#"B> I" I#"#.into(), bool(false)),
+            (r#"B= I# I#"#.into(), bool(true)),
+            (r#"B= S! S!"#.into(), bool(true)),
+            (r#"B= T F"#.into(), bool(false)),
+            (
+                r#"B= I" S!"#.into(),
+                Err(EvalError::TypeMismatch {
+                    op: Operator::Bin(BinOp::Eq),
+                    operands: vec![Kind::Int, Kind::String],
+                }),
+            ),
+            (r#"B| F T"#.into(), bool(true)),
+            (r#"B& T F"#.into(), bool(false)),
+            (r#"B. S! S""#.into(), string("ab")),
+            (r#"BT I$ S4%34"#.into(), string("tes")),
+            (r#"BT I! S4%34"#.into(), string("")),
+            (r#"BT I( S4%34"#.into(), string("test")),
+            (format!("BT {HUGE} S4%34"), string("test")),
+            (
+                r#"BT U- I" S4%34"#.into(),
+                out_of_range(Operator::Bin(BinOp::Take), -1),
+            ),
+            (r#"BD I" S4%34"#.into(), string("est")),
+            (r#"BD I( S4%34"#.into(), string("")),
+            (format!("BD {HUGE} S4%34"), string("")),
+            (
+                r#"BD U- I" S4%34"#.into(),
+                out_of_range(Operator::Bin(BinOp::Drop), -1),
+            ),
+            (r#"B$ L" v" I""#.into(), int(1)),
+            (r#"B~ L" v" I""#.into(), int(1)),
+            (r#"B! L" v" I""#.into(), int(1)),
+        ];
+
+        // Every operator is covered at the top level of some program.
+        let exprs: Vec<Expr> = table.iter().map(|(p, _)| p.parse().unwrap()).collect();
+        for op in Operator::all().filter(|op| *op != Operator::If) {
+            assert!(
+                exprs.iter().any(|e| match e {
+                    Expr::Un(op2, _) => op == Operator::Un(*op2),
+                    Expr::Bin(op2, _, _) => op == Operator::Bin(*op2),
+                    _ => false,
+                }),
+                "{op} is not covered"
+            );
+        }
+
+        for strategy in [Strategy::Substitution, Strategy::Environment] {
+            let options = EvalOptions {
+                strategy,
+                ..Default::default()
+            };
+            for ((program, expected), expr) in table.iter().zip(&exprs) {
+                assert_eq!(&eval_with(expr, &options), expected, "{program}");
+            }
+        }
+    }
+
+    #[test]
+    fn limits() {
+        // (λv0. v0 v0) (λv0. v0 v0)
+        let omega: Expr = "B$ L! B$ v! v! L! B$ v! v!".parse().unwrap();
+
+        for strategy in [Strategy::Substitution, Strategy::Environment] {
+            let options = EvalOptions {
+                strategy,
+                max_beta_reductions: Some(100),
+                ..Default::default()
+            };
+            assert!(matches!(
+                eval_with(&omega, &options),
+                Err(EvalError::LimitExceeded {
+                    limit: Limit::BetaReductions(100),
+                    beta_reductions: 101,
+                    ..
+                })
+            ));
+
+            let options = EvalOptions {
+                strategy,
+                max_int_bits: Some(64),
+                ..Default::default()
+            };
+            let expr: Expr = "B* I~~~~~~ I~~~~~~".parse().unwrap();
+            assert!(matches!(
+                eval_with(&expr, &options),
+                Err(EvalError::LimitExceeded {
+                    limit: Limit::IntBits(64),
+                    ..
+                })
+            ));
+        }
+
+        // The substitution evaluator would overflow the stack before timing out.
+        let options = EvalOptions {
+            timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        assert!(matches!(
+            eval_with(&omega, &options),
+            Err(EvalError::LimitExceeded {
+                limit: Limit::Time(_),
+                ..
+            })
+        ));
+
+        // (λv0. 1 + v0 v0) (λv0. 1 + v0 v0) keeps pending additions.
+        let expr: Expr = r#"B$ L! B+ I" B$ v! v! L! B+ I" B$ v! v!"#.parse().unwrap();
+        let options = EvalOptions {
+            max_stack_depth: Some(1000),
+            ..Default::default()
+        };
+        assert!(matches!(
+            eval_with(&expr, &options),
+            Err(EvalError::LimitExceeded {
+                limit: Limit::StackDepth(1000),
+                ..
+            })
+        ));
+
+        // (λv0. v0 + (v0 + (... + v0))) 0 has more than 1000 nodes after
+        // substitution.
+        let expr: Expr = format!("B$ L! {}v! I!", "B+ v! ".repeat(600))
+            .parse()
+            .unwrap();
+        let options = EvalOptions {
+            strategy: Strategy::Substitution,
+            max_term_size: Some(1000),
+            ..Default::default()
+        };
+        assert!(matches!(
+            eval_with(&expr, &options),
+            Err(EvalError::LimitExceeded {
+                limit: Limit::TermSize(1000),
+                ..
+            })
+        ));
+    }
+
+    // cargo test -p common --release bench_strategies -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_strategies() {
+        for (name, program) in [
+            ("language_test", LANGUAGE_TEST),
+            ("beta_reductions", BETA_REDUCTIONS),
+        ] {
+            let expr: Expr = program.parse().unwrap();
+            for strategy in [Strategy::Substitution, Strategy::Environment] {
+                let options = EvalOptions {
+                    strategy,
+                    ..Default::default()
+                };
+                let start = std::time::Instant::now();
+                for _ in 0..1000 {
+                    eval_with(&expr, &options).unwrap();
+                }
+                eprintln!("{name} {strategy:?}: {:?}", start.elapsed() / 1000);
+            }
+        }
+    }
+
+    #[test]
+    fn tail_call() {
+        // (
+        //     fix
+        //     (fn f n r ->
+        //         (if (== n 0) {
+        //             r
+        //         } else {
+        //             (f (- n 1) (+ r 1))
+        //         })
+        //     )
+        //     1000000 0
+        // )
+        let expr: Expr = r#"B$ B$ B$ L& B$ L8 B$ v& B$ v8 v8 L8 B$ v& B$ v8 v8 L& L. L2 ? B= v. I! v2 B$ B$ v& B- v. I" B+ v2 I" I"41= I!"#.parse().unwrap();
+        assert_eq!(
+            eval(&expr).unwrap(),
+            Expr::Int(BigInt::from(1000000).into())
+        );
+    }
+
+    #[test]
+    fn long_thunk_chain() {
+        // The same loop returning 0, so the accumulator is never forced and
+        // ends up as a chain of 100000 thunks (((0 + 1) + 1) + ...), each
+        // referring to the previous one through its environment. Dropping it
+        // must not overflow the stack.
+        let expr: Expr = r#"B$ B$ B$ L& B$ L8 B$ v& B$ v8 v8 L8 B$ v& B$ v8 v8 L& L. L2 ? B= v. I! I! B$ B$ v& B- v. I" B+ v2 I" I,>o I!"#.parse().unwrap();
+        assert_eq!(eval(&expr).unwrap(), Expr::Int(BigInt::from(0).into()));
+    }
+}
@@ -0,0 +1,100 @@
+//! Strong normalization, i.e. reduction under lambdas.
+//!
+//! Terms are reduced in normal order: the head of a term is reduced first,
+//! and then its subterms, including lambda bodies. Inside a lambda body its
+//! variable is free, so operators and conditionals on it cannot be reduced
+//! and are left as is. Operators failing on literals (e.g. a division by zero
+//! in a branch never taken) are left as is too, since they may never run.
+//!
+//! Call-by-need applications are reduced like call-by-name ones. Functions
+//! made recursive with a fixed-point combinator have no normal form, so this
+//! only terminates on them thanks to the limits of `EvalOptions`.
+
+use super::{apply_bin, apply_un, substitute, EvalError, Meter, Operator};
+use crate::expr::{BinOp, Expr};
+
+pub(super) fn normalize(e: &Expr, meter: &mut Meter) -> Result<Expr, EvalError> {
+    Normalizer { meter, depth: 0 }.nf(e)
+}
+
+struct Normalizer<'a, 'b> {
+    meter: &'a mut Meter<'b>,
+    depth: usize,
+}
+
+impl Normalizer<'_, '_> {
+    fn nf(&mut self, e: &Expr) -> Result<Expr, EvalError> {
+        self.depth += 1;
+        self.meter.depth(self.depth);
+        let e = self.whnf(e)?;
+        let ret = match &e {
+            Expr::Un(op, e) => Expr::Un(*op, self.nf(e)?.into()),
+            Expr::Bin(op, l, r) => Expr::Bin(*op, self.nf(l)?.into(), self.nf(r)?.into()),
+            Expr::If(cond, th, el) => Expr::If(
+                self.nf(cond)?.into(),
+                self.nf(th)?.into(),
+                self.nf(el)?.into(),
+            ),
+            Expr::Lambda(v, body) => Expr::Lambda(*v, self.nf(body)?.into()),
+            _ => e,
+        };
+        self.depth -= 1;
+        Ok(ret)
+    }
+
+    /// Reduces the head of `e` until it is a literal, a lambda, or stuck on a
+    /// free variable.
+    fn whnf(&mut self, e: &Expr) -> Result<Expr, EvalError> {
+        let mut e = e.clone();
+        loop {
+            e = match &e {
+                Expr::Bin(op @ (BinOp::App | BinOp::AppL | BinOp::AppV), f, arg) => {
+                    let f = self.whnf(f)?;
+                    let Expr::Lambda(v, body) = &f else {
+                        return Ok(Expr::Bin(*op, f.into(), arg.clone()));
+                    };
+                    let arg = if *op == BinOp::AppV {
+                        self.nf(arg)?
+                    } else {
+                        arg.as_ref().clone()
+                    };
+                    let e = substitute(body, *v, &arg);
+                    self.meter.beta(*op)?;
+                    self.meter.term_size(e.num_nodes())?;
+                    e
+                }
+                Expr::Un(op, x) => {
+                    let x = self.whnf(x)?;
+                    if let (true, Ok(ret)) = (x.is_nf(), apply_un(*op, &x)) {
+                        self.meter.op(Operator::Un(*op));
+                        self.meter.check_lit(&ret)?;
+                        return Ok(ret);
+                    }
+                    return Ok(Expr::Un(*op, x.into()));
+                }
+                Expr::Bin(op, l, r) => {
+                    let l = self.whnf(l)?;
+                    let r = self.whnf(r)?;
+                    if let (true, Ok(ret)) = (l.is_nf() && r.is_nf(), apply_bin(*op, &l, &r)) {
+                        self.meter.op(Operator::Bin(*op));
+                        self.meter.check_lit(&ret)?;
+                        return Ok(ret);
+                    }
+                    return Ok(Expr::Bin(*op, l.into(), r.into()));
+                }
+                Expr::If(cond, th, el) => match self.whnf(cond)? {
+                    Expr::Bool(b) => {
+                        self.meter.op(Operator::If);
+                        if b {
+                            th.as_ref().clone()
+                        } else {
+                            el.as_ref().clone()
+                        }
+                    }
+                    cond => return Ok(Expr::If(cond.into(), th.clone(), el.clone())),
+                },
+                _ => return Ok(e),
+            };
+        }
+    }
+}
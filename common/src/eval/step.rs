@@ -0,0 +1,291 @@
+//! Single-step reducer, for tracing how an expression is evaluated.
+//!
+//! Each step contracts the leftmost-outermost redex that the evaluator would
+//! reduce next, until the expression becomes a value (a literal or a lambda).
+//! Sharing is modeled as in the substitution evaluator: the argument of a `B~`
+//! is set aside as a thunk, which is reduced in place the first time it is
+//! needed and reused afterwards, so the steps match `eval_with_stats`.
+
+use std::rc::Rc;
+
+use super::{
+    apply_bin, apply_un, below_thunk_vars, error::type_mismatch, substitute, unshare, EvalError,
+    Operator, THUNK_VAR_BASE,
+};
+use crate::expr::{BinOp, Expr};
+
+/// The rule applied by a reduction step.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Rule {
+    /// Beta reduction by an application operator.
+    Beta(BinOp),
+    /// A unary or binary operator other than applications.
+    Builtin(Operator),
+    /// A conditional taking the then (true) or else (false) branch.
+    If(bool),
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rule::Beta(op) => write!(f, "beta ({op})"),
+            Rule::Builtin(op) => write!(f, "builtin ({op})"),
+            Rule::If(true) => write!(f, "if (then)"),
+            Rule::If(false) => write!(f, "if (else)"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Step {
+    /// The position of the redex in `Stepper::expr`, as child indices from the
+    /// root. A redex in a shared argument is at its first occurrence.
+    pub path: Vec<usize>,
+    pub rule: Rule,
+    /// The redex before the step.
+    pub redex: Expr,
+}
+
+type Stepped = Option<(Expr, Rule, Expr)>;
+
+pub struct Stepper {
+    expr: Expr,
+    /// The shared arguments of `B~`. The `i`-th one appears in `expr` and the
+    /// other thunks as the variable `THUNK_VAR_BASE + i`.
+    thunks: Vec<Rc<Expr>>,
+    /// A free variable that would be mistaken for a thunk.
+    unbound: Option<usize>,
+    steps: usize,
+}
+
+impl Stepper {
+    pub fn new(expr: Expr) -> Self {
+        let expr = below_thunk_vars(&expr).into_owned();
+        let unbound = expr.free_vars().into_iter().find(|&v| v >= THUNK_VAR_BASE);
+        Stepper {
+            expr,
+            thunks: vec![],
+            unbound,
+            steps: 0,
+        }
+    }
+
+    /// The current expression, with the shared arguments in place of their
+    /// thunks.
+    pub fn expr(&self) -> Expr {
+        unshare(&self.expr, &|i| self.thunks.get(i).map(|t| t.as_ref()))
+    }
+
+    pub fn into_expr(self) -> Expr {
+        self.expr()
+    }
+
+    /// The number of steps performed so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Performs one reduction. Returns `None` if the expression is already a
+    /// value.
+    pub fn step(&mut self) -> Result<Option<Step>, EvalError> {
+        if let Some(v) = self.unbound {
+            return Err(EvalError::UnboundVariable(v));
+        }
+        let mut path = vec![];
+        let expr = self.expr.clone();
+        let Some((e, rule, redex)) = self.step_expr(&expr, &mut path)? else {
+            return Ok(None);
+        };
+        self.expr = e;
+        self.steps += 1;
+        let redex = unshare(&redex, &|i| self.thunks.get(i).map(|t| t.as_ref()));
+        Ok(Some(Step { path, rule, redex }))
+    }
+
+    fn thunk(&self, v: usize) -> Option<Rc<Expr>> {
+        let i = v.checked_sub(THUNK_VAR_BASE)?;
+        self.thunks.get(i).cloned()
+    }
+
+    /// The value of `e` if it is a thunk that has been reduced to one, or `e`.
+    fn resolve(&self, e: &Rc<Expr>) -> Rc<Expr> {
+        if let Expr::Var(v) = e.as_ref() {
+            if let Some(thunk) = self.thunk(*v) {
+                let thunk = self.resolve(&thunk);
+                if is_value(&thunk) {
+                    return thunk;
+                }
+            }
+        }
+        e.clone()
+    }
+
+    /// Reduces the next redex in `e`, and returns the new expression, the rule
+    /// applied and the redex. `path` is left pointing at the redex.
+    fn step_expr(&mut self, e: &Expr, path: &mut Vec<usize>) -> Result<Stepped, EvalError> {
+        let (e2, rule) = match e {
+            Expr::Bool(_) | Expr::Int(_) | Expr::String(_) | Expr::Lambda(_, _) => return Ok(None),
+            Expr::Var(v) => {
+                let Some(thunk) = self.thunk(*v) else {
+                    return Err(EvalError::UnboundVariable(*v));
+                };
+                // Reduces the shared argument in place, for every reference.
+                let Some((thunk, rule, redex)) = self.step_expr(&thunk, path)? else {
+                    return Ok(None);
+                };
+                self.thunks[*v - THUNK_VAR_BASE] = thunk.into();
+                return Ok(Some((e.clone(), rule, redex)));
+            }
+            Expr::Un(op, x) => {
+                let x = self.resolve(x);
+                if !is_value(&x) {
+                    return self.step_child(0, &x, path, &|x| Expr::Un(*op, x));
+                }
+                (apply_un(*op, &x)?, Rule::Builtin(Operator::Un(*op)))
+            }
+            Expr::Bin(op @ (BinOp::App | BinOp::AppL | BinOp::AppV), f, a) => {
+                let f = self.resolve(f);
+                match f.as_ref() {
+                    Expr::Lambda(v, body) => {
+                        let a = self.resolve(a);
+                        let arg = match op {
+                            BinOp::AppV if !is_value(&a) => {
+                                let f = f.clone();
+                                return self
+                                    .step_child(1, &a, path, &|a| Expr::Bin(*op, f.clone(), a));
+                            }
+                            // Values are cheap to copy, and a thunk is already
+                            // shared.
+                            BinOp::AppL if !is_value(&a) && !self.is_thunk(&a) => {
+                                self.thunks.push(a);
+                                Expr::Var(THUNK_VAR_BASE + self.thunks.len() - 1)
+                            }
+                            _ => a.as_ref().clone(),
+                        };
+                        (substitute(body, *v, &arg), Rule::Beta(*op))
+                    }
+                    f2 if is_value(f2) => return Err(type_mismatch(Operator::Bin(*op), &[f2])),
+                    _ => return self.step_child(0, &f, path, &|f| Expr::Bin(*op, f, a.clone())),
+                }
+            }
+            Expr::Bin(op, l, r) => {
+                let l = self.resolve(l);
+                if !is_value(&l) {
+                    return self.step_child(0, &l, path, &|l| Expr::Bin(*op, l, r.clone()));
+                }
+                let r = self.resolve(r);
+                if !is_value(&r) {
+                    return self.step_child(1, &r, path, &|r| Expr::Bin(*op, l.clone(), r));
+                }
+                (apply_bin(*op, &l, &r)?, Rule::Builtin(Operator::Bin(*op)))
+            }
+            Expr::If(cond, th, el) => {
+                let cond = self.resolve(cond);
+                match cond.as_ref() {
+                    Expr::Bool(true) => (th.as_ref().clone(), Rule::If(true)),
+                    Expr::Bool(false) => (el.as_ref().clone(), Rule::If(false)),
+                    c if is_value(c) => return Err(type_mismatch(Operator::If, &[c])),
+                    _ => {
+                        return self
+                            .step_child(0, &cond, path, &|c| Expr::If(c, th.clone(), el.clone()))
+                    }
+                }
+            }
+        };
+        Ok(Some((e2, rule, e.clone())))
+    }
+
+    /// Steps into the `i`-th child of the current node, and rebuilds the node
+    /// around the result.
+    fn step_child(
+        &mut self,
+        i: usize,
+        child: &Expr,
+        path: &mut Vec<usize>,
+        rebuild: &dyn Fn(Rc<Expr>) -> Expr,
+    ) -> Result<Stepped, EvalError> {
+        path.push(i);
+        Ok(self
+            .step_expr(child, path)?
+            .map(|(c, rule, redex)| (rebuild(c.into()), rule, redex)))
+    }
+
+    fn is_thunk(&self, e: &Expr) -> bool {
+        matches!(e, Expr::Var(v) if self.thunk(*v).is_some())
+    }
+}
+
+fn is_value(e: &Expr) -> bool {
+    e.is_nf() || matches!(e, Expr::Lambda(_, _))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::{eval_with_stats, EvalOptions};
+
+    #[test]
+    fn steps() {
+        // (λv1. v1 + 1) (if T then 2 else 3)
+        let expr: Expr = r#"B$ L" B+ v" I" ? T I# I$"#.parse().unwrap();
+        let mut stepper = Stepper::new(expr);
+
+        let mut steps = vec![];
+        while let Some(step) = stepper.step().unwrap() {
+            steps.push((step.path, step.rule));
+        }
+        assert_eq!(
+            steps,
+            vec![
+                (vec![], Rule::Beta(BinOp::App)),
+                (vec![0], Rule::If(true)),
+                (vec![], Rule::Builtin(Operator::Bin(BinOp::Add))),
+            ]
+        );
+        assert_eq!(stepper.steps(), 3);
+        assert_eq!(
+            stepper.into_expr(),
+            Expr::Int(num_bigint::BigInt::from(3).into())
+        );
+    }
+
+    #[test]
+    fn agrees_with_eval() {
+        let expr: Expr = r#"B$ B$ L" B$ L# B$ v" B$ v# v# L# B$ v" B$ v# v# L" L# ? B= v# I! I" B$ L$ B+ B$ v" v$ B$ v" v$ B- v# I" I%"#.parse().unwrap();
+        let mut stepper = Stepper::new(expr.clone());
+        let mut betas = 0;
+        while let Some(step) = stepper.step().unwrap() {
+            if matches!(step.rule, Rule::Beta(_)) {
+                betas += 1;
+            }
+        }
+        assert_eq!(stepper.expr(), crate::eval::eval(&expr).unwrap());
+        assert_eq!(betas, 109);
+    }
+
+    #[test]
+    fn shares_call_by_need_arguments() {
+        // (λv2. v2 + v2) ~ ((λv1. v1 * v1) 2), and a returned lambda that
+        // refers to a thunk.
+        for (s, expected) in [
+            (r#"B~ L# B+ v# v# B$ L" B* v" v" I#"#, "I)"),
+            (
+                r#"B~ L# L$ B+ v# v$ B$ L" B* v" v" I#"#,
+                r#"L$ B+ B$ L" B* v" v" I# v$"#,
+            ),
+        ] {
+            let expr: Expr = s.parse().unwrap();
+            let mut stepper = Stepper::new(expr.clone());
+            let mut betas = 0;
+            while let Some(step) = stepper.step().unwrap() {
+                if matches!(step.rule, Rule::Beta(_)) {
+                    betas += 1;
+                }
+            }
+            let (value, stats) = eval_with_stats(&expr, &EvalOptions::default()).unwrap();
+            assert_eq!(stepper.expr(), value);
+            assert_eq!(stepper.expr().encoded().to_string(), expected);
+            assert_eq!(betas, stats.beta_reductions, "{s}");
+        }
+    }
+}
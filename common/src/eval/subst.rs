@@ -0,0 +1,280 @@
+//! Capture-avoiding substitution on terms with named variables.
+
+use std::collections::BTreeSet;
+
+use crate::expr::Expr;
+
+/// Substitutes `arg` for the free occurrences of `v` in `e`. Lambdas in `e`
+/// binding a variable that is free in `arg` are renamed to fresh variables,
+/// so that the substitution does not capture it.
+pub fn substitute(e: &Expr, v: usize, arg: &Expr) -> Expr {
+    Substitution {
+        v,
+        arg,
+        arg_free_vars: None,
+        fresh: Fresh::Unused(e),
+    }
+    .apply(e)
+}
+
+struct Substitution<'a> {
+    v: usize,
+    arg: &'a Expr,
+    /// Computed on the first lambda, as arguments are usually closed and most
+    /// bodies have no lambdas.
+    arg_free_vars: Option<BTreeSet<usize>>,
+    fresh: Fresh<'a>,
+}
+
+/// Generates variables not used in the substituted term nor the argument.
+enum Fresh<'a> {
+    /// No variable is generated yet. Holds the term to substitute into.
+    Unused(&'a Expr),
+    Next(usize),
+}
+
+impl Substitution<'_> {
+    fn fresh(&mut self) -> usize {
+        let next = match self.fresh {
+            Fresh::Unused(e) => max_var(e).max(max_var(self.arg)).max(self.v) + 1,
+            Fresh::Next(next) => next,
+        };
+        self.fresh = Fresh::Next(next + 1);
+        next
+    }
+
+    fn apply(&mut self, e: &Expr) -> Expr {
+        match e {
+            Expr::Var(w) if *w == self.v => self.arg.clone(),
+            Expr::Un(op, e) => Expr::Un(*op, self.apply(e).into()),
+            Expr::Bin(op, l, r) => Expr::Bin(*op, self.apply(l).into(), self.apply(r).into()),
+            Expr::If(cond, th, el) => Expr::If(
+                self.apply(cond).into(),
+                self.apply(th).into(),
+                self.apply(el).into(),
+            ),
+            Expr::Lambda(w, _) if *w == self.v => e.clone(),
+            Expr::Lambda(w, body) => {
+                let arg = self.arg;
+                let free = self.arg_free_vars.get_or_insert_with(|| arg.free_vars());
+                if free.contains(w) {
+                    // The fresh variable does not occur in `body`, so renaming
+                    // cannot capture anything.
+                    let fresh = self.fresh();
+                    let body = substitute(body, *w, &Expr::Var(fresh));
+                    Expr::Lambda(fresh, self.apply(&body).into())
+                } else {
+                    Expr::Lambda(*w, self.apply(body).into())
+                }
+            }
+            _ => e.clone(),
+        }
+    }
+}
+
+/// The largest variable number used in `e`.
+pub(super) fn max_var(e: &Expr) -> usize {
+    match e {
+        Expr::Var(v) => *v,
+        Expr::Un(_, e) => max_var(e),
+        Expr::Bin(_, l, r) => max_var(l).max(max_var(r)),
+        Expr::If(cond, th, el) => max_var(cond).max(max_var(th)).max(max_var(el)),
+        Expr::Lambda(v, e) => (*v).max(max_var(e)),
+        _ => 0,
+    }
+}
+
+/// Whether `a` and `b` are the same up to the names of bound variables.
+pub fn alpha_eq(a: &Expr, b: &Expr) -> bool {
+    fn go(a: &Expr, b: &Expr, bound_a: &mut Vec<usize>, bound_b: &mut Vec<usize>) -> bool {
+        match (a, b) {
+            // Compare the de Bruijn indices of bound variables, and the names
+            // of free ones.
+            (Expr::Var(x), Expr::Var(y)) => {
+                match (
+                    bound_a.iter().rposition(|v| v == x),
+                    bound_b.iter().rposition(|v| v == y),
+                ) {
+                    (Some(i), Some(j)) => bound_a.len() - i == bound_b.len() - j,
+                    (None, None) => x == y,
+                    _ => false,
+                }
+            }
+            (Expr::Un(op1, e1), Expr::Un(op2, e2)) => op1 == op2 && go(e1, e2, bound_a, bound_b),
+            (Expr::Bin(op1, l1, r1), Expr::Bin(op2, l2, r2)) => {
+                op1 == op2 && go(l1, l2, bound_a, bound_b) && go(r1, r2, bound_a, bound_b)
+            }
+            (Expr::If(c1, t1, e1), Expr::If(c2, t2, e2)) => {
+                go(c1, c2, bound_a, bound_b)
+                    && go(t1, t2, bound_a, bound_b)
+                    && go(e1, e2, bound_a, bound_b)
+            }
+            (Expr::Lambda(x, e1), Expr::Lambda(y, e2)) => {
+                bound_a.push(*x);
+                bound_b.push(*y);
+                let ret = go(e1, e2, bound_a, bound_b);
+                bound_a.pop();
+                bound_b.pop();
+                ret
+            }
+            _ => a == b,
+        }
+    }
+
+    go(a, b, &mut vec![], &mut vec![])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::expr::{BinOp, UnOp};
+
+    /// The reference: terms with de Bruijn indices for bound variables and
+    /// names for free ones.
+    #[derive(PartialEq, Eq, Clone, Debug)]
+    enum Term {
+        Bound(usize),
+        Free(usize),
+        Lit(Expr),
+        Un(UnOp, Box<Term>),
+        Bin(BinOp, Box<Term>, Box<Term>),
+        If(Box<Term>, Box<Term>, Box<Term>),
+        Lambda(Box<Term>),
+    }
+
+    fn to_term(e: &Expr, bound: &mut Vec<usize>) -> Term {
+        match e {
+            Expr::Var(v) => match bound.iter().rposition(|w| w == v) {
+                Some(i) => Term::Bound(bound.len() - 1 - i),
+                None => Term::Free(*v),
+            },
+            Expr::Un(op, e) => Term::Un(*op, to_term(e, bound).into()),
+            Expr::Bin(op, l, r) => {
+                Term::Bin(*op, to_term(l, bound).into(), to_term(r, bound).into())
+            }
+            Expr::If(cond, th, el) => Term::If(
+                to_term(cond, bound).into(),
+                to_term(th, bound).into(),
+                to_term(el, bound).into(),
+            ),
+            Expr::Lambda(v, e) => {
+                bound.push(*v);
+                let e = to_term(e, bound);
+                bound.pop();
+                Term::Lambda(e.into())
+            }
+            _ => Term::Lit(e.clone()),
+        }
+    }
+
+    /// Substitutes `arg` for the index `depth` in `t`. `arg` has no free
+    /// indices, so it needs no shifting.
+    fn instantiate(t: &Term, depth: usize, arg: &Term) -> Term {
+        match t {
+            Term::Bound(i) if *i == depth => arg.clone(),
+            Term::Un(op, e) => Term::Un(*op, instantiate(e, depth, arg).into()),
+            Term::Bin(op, l, r) => Term::Bin(
+                *op,
+                instantiate(l, depth, arg).into(),
+                instantiate(r, depth, arg).into(),
+            ),
+            Term::If(cond, th, el) => Term::If(
+                instantiate(cond, depth, arg).into(),
+                instantiate(th, depth, arg).into(),
+                instantiate(el, depth, arg).into(),
+            ),
+            Term::Lambda(e) => Term::Lambda(instantiate(e, depth + 1, arg).into()),
+            _ => t.clone(),
+        }
+    }
+
+    /// Generates terms over a few variables, so that shadowing and capture
+    /// are common.
+    fn random_expr(rng: &mut StdRng, size: usize) -> Expr {
+        if size <= 1 {
+            return match rng.gen_range(0..4) {
+                0 => Expr::Int(num_bigint::BigInt::from(rng.gen_range(0..3)).into()),
+                _ => Expr::Var(rng.gen_range(1..5)),
+            };
+        }
+        match rng.gen_range(0..5) {
+            0 => Expr::Un(UnOp::Neg, random_expr(rng, size - 1).into()),
+            1 | 2 => {
+                let l = rng.gen_range(1..size);
+                let op = [BinOp::Add, BinOp::App][rng.gen_range(0..2)];
+                Expr::Bin(
+                    op,
+                    random_expr(rng, l).into(),
+                    random_expr(rng, size - l).into(),
+                )
+            }
+            3 if size >= 3 => {
+                let c = rng.gen_range(1..size - 1);
+                let t = rng.gen_range(1..size - c);
+                Expr::If(
+                    random_expr(rng, c).into(),
+                    random_expr(rng, t).into(),
+                    random_expr(rng, size - c - t).into(),
+                )
+            }
+            _ => Expr::Lambda(rng.gen_range(1..5), random_expr(rng, size - 1).into()),
+        }
+    }
+
+    #[test]
+    fn capture() {
+        // (λv2. λv1. v2) v1 must not become λv1. v1.
+        let e = substitute(&Expr::Lambda(1, Expr::Var(2).into()), 2, &Expr::Var(1));
+        assert_eq!(e, Expr::Lambda(3, Expr::Var(1).into()));
+        // Shadowed variables are not substituted.
+        let lambda = Expr::Lambda(2, Expr::Var(2).into());
+        assert_eq!(substitute(&lambda, 2, &Expr::Var(1)), lambda);
+    }
+
+    #[test]
+    fn substitute_agrees_with_de_bruijn() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..10000 {
+            let v = rng.gen_range(1..5);
+            let size = rng.gen_range(1..12);
+            let body = random_expr(&mut rng, size);
+            let size = rng.gen_range(1..6);
+            let arg = random_expr(&mut rng, size);
+
+            let actual = to_term(&substitute(&body, v, &arg), &mut vec![]);
+            let Term::Lambda(t) = to_term(&Expr::Lambda(v, Rc::new(body.clone())), &mut vec![])
+            else {
+                unreachable!()
+            };
+            let expected = instantiate(&t, 0, &to_term(&arg, &mut vec![]));
+            assert_eq!(actual, expected, "{body} [{v} := {arg}]");
+        }
+    }
+
+    #[test]
+    fn alpha_eq_agrees_with_de_bruijn() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..10000 {
+            let size = rng.gen_range(1..8);
+            let a = random_expr(&mut rng, size);
+            let size = rng.gen_range(1..8);
+            let b = random_expr(&mut rng, size);
+            assert_eq!(
+                alpha_eq(&a, &b),
+                to_term(&a, &mut vec![]) == to_term(&b, &mut vec![]),
+                "{a} {b}"
+            );
+
+            // Renaming a bound variable keeps the term alpha-equivalent.
+            if let Expr::Lambda(v, body) = &a {
+                let fresh = max_var(&a) + 1;
+                let renamed = Expr::Lambda(fresh, substitute(body, *v, &Expr::Var(fresh)).into());
+                assert!(alpha_eq(&a, &renamed), "{a} {renamed}");
+            }
+        }
+    }
+}
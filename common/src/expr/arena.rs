@@ -0,0 +1,377 @@
+//! Hash-consed expressions stored in an arena.
+//!
+//! Every distinct subexpression is stored once and referred to by an
+//! `ExprId`, so programs built from repeated parts take memory for their
+//! distinct parts only, and equal subexpressions have equal ids. Children are
+//! always added before their parents, so ids are in topological order and
+//! whole programs are processed with loops over ids instead of recursion.
+//! This keeps encoding, decoding and conversions linear in the size of the
+//! program, whatever its depth.
+
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+
+use super::parse::{build, Builder, ParseError};
+use super::{BinOp, Expr, Token, TokenReader, UnOp};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub struct ExprId(u32);
+
+impl ExprId {
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A node of the arena, like `Expr` with ids for children.
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum Node {
+    Bool(bool),
+    Int(Rc<BigInt>),
+    String(Rc<String>),
+    Var(usize),
+    Un(UnOp, ExprId),
+    Bin(BinOp, ExprId, ExprId),
+    If(ExprId, ExprId, ExprId),
+    Lambda(usize, ExprId),
+}
+
+impl Node {
+    pub fn children(&self) -> Vec<ExprId> {
+        match self {
+            Node::Un(_, e) | Node::Lambda(_, e) => vec![*e],
+            Node::Bin(_, l, r) => vec![*l, *r],
+            Node::If(cond, th, el) => vec![*cond, *th, *el],
+            _ => vec![],
+        }
+    }
+
+    fn token(&self) -> Token {
+        match self {
+            Node::Bool(b) => Token::Bool(*b),
+            Node::Int(n) => Token::Int(n.as_ref().clone()),
+            Node::String(s) => Token::String(s.as_ref().clone()),
+            Node::Var(v) => Token::Var(*v),
+            Node::Un(op, _) => Token::Un(*op),
+            Node::Bin(op, _, _) => Token::Bin(*op),
+            Node::If(..) => Token::If,
+            Node::Lambda(v, _) => Token::Lambda(*v),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Arena {
+    nodes: Vec<Node>,
+    ids: HashMap<Node, ExprId>,
+}
+
+impl Arena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of distinct nodes.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn node(&self, id: ExprId) -> &Node {
+        &self.nodes[id.index()]
+    }
+
+    /// Returns the id of `node`, adding it if it is new. The children must be
+    /// in this arena.
+    pub fn add(&mut self, node: Node) -> ExprId {
+        if let Some(&id) = self.ids.get(&node) {
+            return id;
+        }
+        debug_assert!(node.children().iter().all(|c| c.index() < self.len()));
+        let id = ExprId(self.nodes.len().try_into().expect("too many nodes"));
+        self.nodes.push(node.clone());
+        self.ids.insert(node, id);
+        id
+    }
+
+    pub fn bool(&mut self, b: bool) -> ExprId {
+        self.add(Node::Bool(b))
+    }
+
+    pub fn int(&mut self, n: impl Into<BigInt>) -> ExprId {
+        self.add(Node::Int(Rc::new(n.into())))
+    }
+
+    pub fn string(&mut self, s: impl Into<String>) -> ExprId {
+        self.add(Node::String(Rc::new(s.into())))
+    }
+
+    pub fn var(&mut self, v: usize) -> ExprId {
+        self.add(Node::Var(v))
+    }
+
+    pub fn un(&mut self, op: UnOp, e: ExprId) -> ExprId {
+        self.add(Node::Un(op, e))
+    }
+
+    pub fn bin(&mut self, op: BinOp, l: ExprId, r: ExprId) -> ExprId {
+        self.add(Node::Bin(op, l, r))
+    }
+
+    pub fn if_(&mut self, cond: ExprId, th: ExprId, el: ExprId) -> ExprId {
+        self.add(Node::If(cond, th, el))
+    }
+
+    pub fn lambda(&mut self, v: usize, body: ExprId) -> ExprId {
+        self.add(Node::Lambda(v, body))
+    }
+
+    /// Parses an encoded program into the arena.
+    pub fn parse(&mut self, s: &str) -> Result<ExprId, ParseError> {
+        self.parse_reader(s.as_bytes())
+    }
+
+    /// Parses an encoded program from a reader into the arena.
+    pub fn parse_reader(&mut self, reader: impl BufRead) -> Result<ExprId, ParseError> {
+        build(
+            self,
+            TokenReader::new(reader).map(|token| token.map(|(span, token)| (Some(span), token))),
+        )
+    }
+
+    /// Adds `e`. Subexpressions shared by `Rc` are only visited once.
+    pub fn from_expr(&mut self, e: &Expr) -> ExprId {
+        let mut seen: HashMap<*const Expr, ExprId> = HashMap::new();
+        // Post-order with an explicit stack, as deep programs would overflow
+        // the Rust stack. Each entry is a node and whether its children have
+        // been pushed.
+        let mut stack = vec![(e, false)];
+        let mut done: Vec<ExprId> = vec![];
+        while let Some((e, expanded)) = stack.pop() {
+            if let Some(&id) = seen.get(&(e as *const Expr)) {
+                done.push(id);
+                continue;
+            }
+            if !expanded {
+                stack.push((e, true));
+                stack.extend(e.children().rev().map(|c| (c, false)));
+                continue;
+            }
+            let c = done.split_off(done.len() - e.children().count());
+            let node = match e {
+                Expr::Bool(b) => Node::Bool(*b),
+                Expr::Int(n) => Node::Int(n.clone()),
+                Expr::String(s) => Node::String(s.clone()),
+                Expr::Var(v) => Node::Var(*v),
+                Expr::Un(op, _) => Node::Un(*op, c[0]),
+                Expr::Bin(op, _, _) => Node::Bin(*op, c[0], c[1]),
+                Expr::If(..) => Node::If(c[0], c[1], c[2]),
+                Expr::Lambda(v, _) => Node::Lambda(*v, c[0]),
+            };
+            let id = self.add(node);
+            seen.insert(e, id);
+            done.push(id);
+        }
+        done.pop().unwrap()
+    }
+
+    /// Whether each node is reachable from `root`.
+    fn reachable(&self, root: ExprId) -> Vec<bool> {
+        let mut reachable = vec![false; root.index() + 1];
+        reachable[root.index()] = true;
+        // Children have smaller ids than their parents.
+        for i in (0..=root.index()).rev() {
+            if reachable[i] {
+                for c in self.nodes[i].children() {
+                    reachable[c.index()] = true;
+                }
+            }
+        }
+        reachable
+    }
+
+    /// Converts `id` to an `Expr`, where equal subexpressions share their
+    /// `Rc`.
+    pub fn to_expr(&self, id: ExprId) -> Expr {
+        let reachable = self.reachable(id);
+        let mut exprs: Vec<Option<Rc<Expr>>> = vec![None; id.index() + 1];
+        for (i, node) in self.nodes[..=id.index()].iter().enumerate() {
+            if !reachable[i] {
+                continue;
+            }
+            let c = |id: &ExprId| exprs[id.index()].clone().unwrap();
+            let e = match node {
+                Node::Bool(b) => Expr::Bool(*b),
+                Node::Int(n) => Expr::Int(n.clone()),
+                Node::String(s) => Expr::String(s.clone()),
+                Node::Var(v) => Expr::Var(*v),
+                Node::Un(op, e) => Expr::Un(*op, c(e)),
+                Node::Bin(op, l, r) => Expr::Bin(*op, c(l), c(r)),
+                Node::If(cond, th, el) => Expr::If(c(cond), c(th), c(el)),
+                Node::Lambda(v, body) => Expr::Lambda(*v, c(body)),
+            };
+            exprs[i] = Some(Rc::new(e));
+        }
+        Rc::unwrap_or_clone(exprs.pop().unwrap().unwrap())
+    }
+
+    /// The length of the encoded program, computed without expanding shared
+    /// subexpressions.
+    pub fn size_in_bytes(&self, id: ExprId) -> usize {
+        let reachable = self.reachable(id);
+        let mut sizes = vec![0; id.index() + 1];
+        for (i, node) in self.nodes[..=id.index()].iter().enumerate() {
+            if !reachable[i] {
+                continue;
+            }
+            let digits = |n: &BigInt| n.magnitude().to_radix_be(94).len();
+            let children = node.children();
+            let own = match node {
+                Node::Bool(_) | Node::If(..) => 1,
+                Node::Int(n) => 1 + digits(n),
+                Node::String(s) => 1 + s.chars().count(),
+                Node::Var(v) | Node::Lambda(v, _) => 1 + digits(&BigInt::from(*v)),
+                Node::Un(..) | Node::Bin(..) => 2,
+            };
+            // Each child is preceded by a space.
+            sizes[i] = own + children.iter().map(|c| 1 + sizes[c.index()]).sum::<usize>();
+        }
+        sizes[id.index()]
+    }
+
+    pub fn encoded(&self, id: ExprId) -> ArenaEncoded<'_> {
+        ArenaEncoded { arena: self, id }
+    }
+}
+
+impl Builder for Arena {
+    type Node = ExprId;
+
+    fn leaf(&mut self, token: &Token) -> ExprId {
+        match token {
+            Token::Bool(b) => self.bool(*b),
+            Token::Int(n) => self.int(n.clone()),
+            Token::String(s) => self.string(s.clone()),
+            Token::Var(v) => self.var(*v),
+            _ => unreachable!("not a leaf: {token:?}"),
+        }
+    }
+
+    fn un(&mut self, op: UnOp, e: ExprId) -> ExprId {
+        Arena::un(self, op, e)
+    }
+
+    fn bin(&mut self, op: BinOp, l: ExprId, r: ExprId) -> ExprId {
+        Arena::bin(self, op, l, r)
+    }
+
+    fn if_(&mut self, cond: ExprId, th: ExprId, el: ExprId) -> ExprId {
+        Arena::if_(self, cond, th, el)
+    }
+
+    fn lambda(&mut self, v: usize, body: ExprId) -> ExprId {
+        Arena::lambda(self, v, body)
+    }
+}
+
+pub struct ArenaEncoded<'a> {
+    arena: &'a Arena,
+    id: ExprId,
+}
+
+impl std::fmt::Display for ArenaEncoded<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Pre-order with an explicit stack.
+        let mut stack = vec![self.id];
+        let mut first = true;
+        while let Some(id) = stack.pop() {
+            if !first {
+                write!(f, " ")?;
+            }
+            first = false;
+            let node = self.arena.node(id);
+            write!(f, "{}", node.token().encoded())?;
+            stack.extend(node.children().into_iter().rev());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let programs = [
+            "T",
+            "I/6",
+            "SB%,,/}Q/2,$_",
+            "? B> I# I$ S4% S34",
+            "B$ B$ L# L$ v# B. SB%,,/ S}Q/2,$_ IK",
+            "L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v#",
+        ];
+        for s in programs {
+            let mut arena = Arena::new();
+            let id = arena.parse(s).unwrap();
+            assert_eq!(arena.encoded(id).to_string(), s);
+            assert_eq!(arena.size_in_bytes(id), s.len());
+
+            let expr: Expr = s.parse().unwrap();
+            assert_eq!(arena.to_expr(id), expr);
+            assert_eq!(arena.from_expr(&expr), id);
+        }
+    }
+
+    #[test]
+    fn sharing() {
+        let mut arena = Arena::new();
+        let id = arena.parse("B. B. S! S! B. S! S!").unwrap();
+        // `S!`, `B. S! S!` and the root.
+        assert_eq!(arena.len(), 3);
+        let Node::Bin(BinOp::Concat, l, r) = arena.node(id) else {
+            panic!("not a concatenation");
+        };
+        assert_eq!(l, r);
+
+        let Expr::Bin(_, l, r) = arena.to_expr(id) else {
+            panic!("not a concatenation");
+        };
+        assert!(Rc::ptr_eq(&l, &r));
+    }
+
+    #[test]
+    fn large_programs() {
+        // Doubling a string 40 times gives a program with 2^40 leaves, which
+        // takes 41 nodes in the arena.
+        let mut arena = Arena::new();
+        let mut id = arena.string("a");
+        for _ in 0..40 {
+            id = arena.bin(BinOp::Concat, id, id);
+        }
+        assert_eq!(arena.len(), 41);
+        // `Sa` takes 2 bytes, and `B. x x` takes 4 more than twice `x`.
+        assert_eq!(arena.size_in_bytes(id), 6 * (1 << 40) - 4);
+
+        // A deep program, which would overflow the stack if traversed
+        // recursively.
+        let mut arena = Arena::new();
+        let mut id = arena.string("a");
+        let n = 200_000;
+        for i in 0..n {
+            let s = arena.string(i.to_string());
+            id = arena.bin(BinOp::Concat, s, id);
+        }
+        let encoded = arena.encoded(id).to_string();
+        assert_eq!(encoded.len(), arena.size_in_bytes(id));
+        let mut decoded = Arena::new();
+        let parsed = decoded.parse(&encoded).unwrap();
+        assert_eq!(decoded.encoded(parsed).to_string(), encoded);
+    }
+}
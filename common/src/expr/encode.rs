@@ -0,0 +1,179 @@
+//! Streaming encoding and decoding of `Expr`.
+//!
+//! The encoder walks the expression with an explicit stack and writes each
+//! token as it is reached, so neither deep nor large expressions need an
+//! intermediate token list.
+
+use std::io::{self, BufRead, Write};
+
+use super::{parse_reader, Expr, ParseError};
+use crate::base94::{encode_base94_int, encode_str};
+
+/// The encoded output is buffered and written in chunks of about this size.
+const CHUNK: usize = 1 << 16;
+
+impl Expr {
+    /// Writes the encoded expression to `w`. Fails with `InvalidData` if a
+    /// string has a character outside of the alphabet or an integer is
+    /// negative.
+    pub fn write_encoded(&self, w: &mut impl Write) -> io::Result<()> {
+        // Grows up to about `CHUNK`, so that small expressions stay cheap.
+        let mut buf = vec![];
+        let mut stack = vec![self];
+        let mut first = true;
+        while let Some(e) = stack.pop() {
+            if !first {
+                buf.push(b' ');
+            }
+            first = false;
+            push_token(&mut buf, e)?;
+            stack.extend(e.children().rev());
+            if buf.len() >= CHUNK {
+                w.write_all(&buf)?;
+                buf.clear();
+            }
+        }
+        w.write_all(&buf)
+    }
+
+    /// Reads an encoded expression from `reader`, token by token. This is
+    /// `parse_reader` without the source map.
+    pub fn read_encoded(reader: impl BufRead) -> Result<Expr, ParseError> {
+        parse_reader(reader).map(|(e, _)| e)
+    }
+}
+
+/// Passes the encoded bytes to a formatter, for `ExprEncoded`.
+pub(super) struct FmtWriter<'a, 'b>(pub &'a mut std::fmt::Formatter<'b>);
+
+impl Write for FmtWriter<'_, '_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // The encoding is ASCII.
+        let s = std::str::from_utf8(buf).map_err(|e| invalid_data(e.to_string()))?;
+        self.0.write_str(s).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Appends the token of the root of `e`.
+fn push_token(buf: &mut Vec<u8>, e: &Expr) -> io::Result<()> {
+    match e {
+        Expr::Bool(b) => buf.push(if *b { b'T' } else { b'F' }),
+        Expr::Int(n) => {
+            let digits = encode_base94_int(n).map_err(|err| invalid_data(err.to_string()))?;
+            write!(buf, "I{digits}")?;
+        }
+        Expr::String(s) => {
+            let s = encode_str(s).map_err(|err| invalid_data(err.to_string()))?;
+            write!(buf, "S{s}")?;
+        }
+        Expr::Un(op, _) => write!(buf, "U{}", op.encoded())?,
+        Expr::Bin(op, _, _) => write!(buf, "B{}", op.encoded())?,
+        Expr::If(..) => buf.push(b'?'),
+        Expr::Lambda(v, _) => write!(buf, "L{}", var_digits(*v))?,
+        Expr::Var(v) => write!(buf, "v{}", var_digits(*v))?,
+    }
+    Ok(())
+}
+
+fn var_digits(v: usize) -> String {
+    encode_base94_int(&v.into()).expect("variables are non-negative")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::expr::BinOp;
+
+    fn encode(e: &Expr) -> io::Result<String> {
+        let mut out = vec![];
+        e.write_encoded(&mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn round_trip() {
+        for s in [
+            "T",
+            "I!",
+            "I/6",
+            "SB%,,/}Q/2,$_",
+            "S",
+            "U- I$",
+            "? B> I# I$ S4% S34",
+            "B$ B$ L# L$ v# B. SB%,,/ S}Q/2,$_ IK",
+            "L\"! v\"!",
+        ] {
+            let e: Expr = s.parse().unwrap();
+            assert_eq!(encode(&e).unwrap(), s);
+            assert_eq!(Expr::read_encoded(s.as_bytes()).unwrap(), e);
+        }
+    }
+
+    #[test]
+    fn unencodable() {
+        let e = Expr::String(Rc::new("caf\u{e9}".to_string()));
+        let err = encode(&e).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let e = Expr::Int(Rc::new((-1).into()));
+        assert_eq!(encode(&e).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Concatenates `2^depth` strings of 16 characters.
+    fn large_expr(depth: usize) -> Expr {
+        if depth == 0 {
+            return Expr::String(Rc::new("Hello, World! 0\n".to_string()));
+        }
+        let l = large_expr(depth - 1);
+        let r = large_expr(depth - 1);
+        Expr::Bin(BinOp::Concat, Rc::new(l), Rc::new(r))
+    }
+
+    #[test]
+    fn chunks() {
+        // Spans several chunks.
+        let e = large_expr(13);
+        let s = encode(&e).unwrap();
+        assert!(s.len() > 2 * CHUNK);
+        assert_eq!(s, e.encoded().to_string());
+        assert_eq!(Expr::read_encoded(s.as_bytes()).unwrap(), e);
+    }
+
+    // cargo test -p common --release bench_encoding -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_encoding() {
+        let e = large_expr(18);
+        let mut bytes = vec![];
+        let start = std::time::Instant::now();
+        e.write_encoded(&mut bytes).unwrap();
+        let elapsed = start.elapsed();
+        let mb = bytes.len() as f64 / 1e6;
+        eprintln!("write_encoded: {mb:.1} MB in {elapsed:?}");
+
+        let start = std::time::Instant::now();
+        let tokens: Vec<String> = e
+            .to_tokens()
+            .iter()
+            .map(|t| t.encoded().to_string())
+            .collect();
+        let joined = tokens.join(" ");
+        eprintln!("to_tokens: {mb:.1} MB in {:?}", start.elapsed());
+        assert_eq!(joined.as_bytes(), bytes);
+
+        let start = std::time::Instant::now();
+        let decoded = Expr::read_encoded(bytes.as_slice()).unwrap();
+        eprintln!("read_encoded: {mb:.1} MB in {:?}", start.elapsed());
+        assert_eq!(decoded, e);
+    }
+}
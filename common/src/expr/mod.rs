@@ -0,0 +1,523 @@
+use std::{ops::Range, rc::Rc, str::FromStr};
+
+use num_bigint::BigInt;
+
+use crate::base94::{
+    self, decode_base94_int, decode_char, encode_base94_int, encode_str, Base94Error,
+};
+
+pub mod arena;
+mod encode;
+mod parse;
+mod surface;
+mod visit;
+
+pub use parse::{
+    parse, parse_reader, Diagnostic, ParseError, ParseErrorKind, Position, SourceMap, Span,
+    TokenReader,
+};
+pub use surface::{parse_surface, ExprSurface, SyntaxError};
+pub use visit::{fold_children, walk, Folder, Visitor};
+
+#[derive(Debug)]
+pub enum Token {
+    Bool(bool),
+    Int(BigInt),
+    String(String),
+    Un(UnOp),
+    Bin(BinOp),
+    If,
+    Lambda(usize),
+    Var(usize),
+}
+
+impl Token {
+    /// The encoded token. Displaying it panics if `try_encode` fails.
+    pub fn encoded(&self) -> TokenEncoded {
+        TokenEncoded(self)
+    }
+
+    /// Encodes the token, or reports a negative integer or the characters of a
+    /// string outside of the alphabet.
+    pub fn try_encode(&self) -> base94::Result<String> {
+        Ok(match self {
+            Token::Bool(v) => (if *v { "T" } else { "F" }).to_string(),
+            Token::Int(n) => format!("I{}", encode_base94_int(n)?),
+            Token::String(s) => format!("S{}", encode_str(s)?),
+            Token::Un(op) => format!("U{}", op.encoded()),
+            Token::Bin(op) => format!("B{}", op.encoded()),
+            Token::If => "?".to_string(),
+            Token::Lambda(v) => format!("L{}", encode_base94_int(&(*v).into())?),
+            Token::Var(v) => format!("v{}", encode_base94_int(&(*v).into())?),
+        })
+    }
+}
+
+pub struct TokenEncoded<'a>(&'a Token);
+
+impl std::fmt::Display for TokenEncoded<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.try_encode() {
+            Ok(s) => f.write_str(&s),
+            Err(err) => panic!("{err}"),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub enum UnOp {
+    Neg,
+    Not,
+    StrToInt,
+    IntToStr,
+}
+
+impl std::fmt::Display for UnOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            UnOp::Neg => "-",
+            UnOp::Not => "!",
+            UnOp::StrToInt => "str-to-int",
+            UnOp::IntToStr => "int-to-str",
+        };
+        write!(f, "{op}")
+    }
+}
+
+impl UnOp {
+    pub const ALL: [UnOp; 4] = [UnOp::Neg, UnOp::Not, UnOp::StrToInt, UnOp::IntToStr];
+
+    pub fn encoded(self) -> UnOpEncoded {
+        UnOpEncoded(self)
+    }
+}
+
+pub struct UnOpEncoded(UnOp);
+
+impl std::fmt::Display for UnOpEncoded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self.0 {
+            UnOp::Neg => "-",
+            UnOp::Not => "!",
+            UnOp::StrToInt => "#",
+            UnOp::IntToStr => "$",
+        };
+        write!(f, "{op}")
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Lt,
+    Gt,
+    Eq,
+    Or,
+    And,
+    Concat,
+    Take,
+    Drop,
+    App,
+    AppL,
+    AppV,
+}
+
+impl std::fmt::Display for BinOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+            BinOp::Lt => "<",
+            BinOp::Gt => ">",
+            BinOp::Eq => "=",
+            BinOp::Or => "|",
+            BinOp::And => "&",
+            BinOp::Concat => ".",
+            BinOp::Take => "take",
+            BinOp::Drop => "drop",
+            BinOp::App => "$",
+            BinOp::AppL => "~",
+            BinOp::AppV => "!",
+        };
+        write!(f, "{op}")
+    }
+}
+
+impl BinOp {
+    pub const ALL: [BinOp; 16] = [
+        BinOp::Add,
+        BinOp::Sub,
+        BinOp::Mul,
+        BinOp::Div,
+        BinOp::Mod,
+        BinOp::Lt,
+        BinOp::Gt,
+        BinOp::Eq,
+        BinOp::Or,
+        BinOp::And,
+        BinOp::Concat,
+        BinOp::Take,
+        BinOp::Drop,
+        BinOp::App,
+        BinOp::AppL,
+        BinOp::AppV,
+    ];
+
+    pub fn encoded(self) -> BinOpEncoded {
+        BinOpEncoded(self)
+    }
+}
+
+pub struct BinOpEncoded(BinOp);
+
+impl std::fmt::Display for BinOpEncoded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self.0 {
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Mod => "%",
+            BinOp::Lt => "<",
+            BinOp::Gt => ">",
+            BinOp::Eq => "=",
+            BinOp::Or => "|",
+            BinOp::And => "&",
+            BinOp::Concat => ".",
+            BinOp::Take => "T",
+            BinOp::Drop => "D",
+            BinOp::App => "$",
+            BinOp::AppL => "~",
+            BinOp::AppV => "!",
+        };
+        write!(f, "{op}")
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TokenErrorKind {
+    Empty,
+    UnknownIndicator,
+    UnknownOperator,
+    TrailingCharacters,
+    InvalidDigit,
+    InvalidCharacter,
+    VariableOutOfRange,
+}
+
+/// An error decoding a token, with the byte range of the offending characters
+/// in the token.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct TokenError {
+    pub kind: TokenErrorKind,
+    pub range: Range<usize>,
+    pub token: String,
+}
+
+impl TokenError {
+    fn new(kind: TokenErrorKind, token: &str, range: Range<usize>) -> Self {
+        TokenError {
+            kind,
+            range,
+            token: token.to_string(),
+        }
+    }
+
+    /// The offending characters.
+    pub fn chars(&self) -> &str {
+        &self.token[self.range.clone()]
+    }
+}
+
+impl std::fmt::Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let chars = self.chars();
+        match self.kind {
+            TokenErrorKind::Empty => write!(f, "empty token"),
+            TokenErrorKind::UnknownIndicator => write!(f, "unknown token indicator `{chars}`"),
+            TokenErrorKind::UnknownOperator => match chars {
+                "" => write!(f, "missing operator in `{}`", self.token),
+                _ => write!(f, "unknown operator `{chars}` in `{}`", self.token),
+            },
+            TokenErrorKind::TrailingCharacters => {
+                write!(
+                    f,
+                    "unexpected `{chars}` after `{}`",
+                    &self.token[..self.range.start]
+                )
+            }
+            TokenErrorKind::InvalidDigit => write!(f, "invalid base-94 digit `{chars}`"),
+            TokenErrorKind::InvalidCharacter => write!(f, "invalid character `{chars}` in string"),
+            TokenErrorKind::VariableOutOfRange => {
+                write!(f, "variable number `{chars}` is out of range")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+/// Decodes the base-94 digits in `s[start..]`.
+fn decode_base94_digits(s: &str, start: usize) -> Result<BigInt, TokenError> {
+    decode_base94_int(&s[start..]).map_err(|err| {
+        let Base94Error::InvalidDigit(c) = err else {
+            unreachable!("{err}")
+        };
+        // The first invalid digit is the first occurrence of `c`.
+        let i = start + s[start..].find(c).unwrap();
+        TokenError::new(TokenErrorKind::InvalidDigit, s, i..i + c.len_utf8())
+    })
+}
+
+fn decode_var(s: &str) -> Result<usize, TokenError> {
+    let n = decode_base94_digits(s, 1)?;
+    n.try_into()
+        .map_err(|_| TokenError::new(TokenErrorKind::VariableOutOfRange, s, 1..s.len()))
+}
+
+impl FromStr for Token {
+    type Err = TokenError;
+
+    fn from_str(s: &str) -> Result<Token, TokenError> {
+        let error = |kind, range| TokenError::new(kind, s, range);
+        let ty = s
+            .chars()
+            .next()
+            .ok_or_else(|| error(TokenErrorKind::Empty, 0..0))?;
+        let body = &s[ty.len_utf8()..];
+        // The token without a body, or an operator with a single character.
+        let expect_end = |end: usize, token: Token| {
+            if end < s.len() {
+                Err(error(TokenErrorKind::TrailingCharacters, end..s.len()))
+            } else {
+                Ok(token)
+            }
+        };
+        let op = body.chars().next();
+        let op_end = 1 + op.map_or(0, char::len_utf8);
+        let unknown_op = || error(TokenErrorKind::UnknownOperator, 1..op_end);
+
+        match ty {
+            'T' => expect_end(1, Token::Bool(true)),
+            'F' => expect_end(1, Token::Bool(false)),
+            '?' => expect_end(1, Token::If),
+            'I' => Ok(Token::Int(decode_base94_digits(s, 1)?)),
+            'S' => {
+                let mut ret = String::new();
+                for (i, c) in body.char_indices() {
+                    let i = 1 + i;
+                    let c = decode_char(c).map_err(|_| {
+                        error(TokenErrorKind::InvalidCharacter, i..i + c.len_utf8())
+                    })?;
+                    ret.push(c);
+                }
+                Ok(Token::String(ret))
+            }
+            'U' => {
+                let op = match op {
+                    Some('-') => UnOp::Neg,
+                    Some('!') => UnOp::Not,
+                    Some('#') => UnOp::StrToInt,
+                    Some('$') => UnOp::IntToStr,
+                    _ => return Err(unknown_op()),
+                };
+                expect_end(op_end, Token::Un(op))
+            }
+            'B' => {
+                let op = match op {
+                    Some('+') => BinOp::Add,
+                    Some('-') => BinOp::Sub,
+                    Some('*') => BinOp::Mul,
+                    Some('/') => BinOp::Div,
+                    Some('%') => BinOp::Mod,
+                    Some('<') => BinOp::Lt,
+                    Some('>') => BinOp::Gt,
+                    Some('=') => BinOp::Eq,
+                    Some('|') => BinOp::Or,
+                    Some('&') => BinOp::And,
+                    Some('.') => BinOp::Concat,
+                    Some('T') => BinOp::Take,
+                    Some('D') => BinOp::Drop,
+                    Some('$') => BinOp::App,
+                    Some('~') => BinOp::AppL,
+                    Some('!') => BinOp::AppV,
+                    _ => return Err(unknown_op()),
+                };
+                expect_end(op_end, Token::Bin(op))
+            }
+            'L' => Ok(Token::Lambda(decode_var(s)?)),
+            'v' => Ok(Token::Var(decode_var(s)?)),
+            _ => Err(error(TokenErrorKind::UnknownIndicator, 0..ty.len_utf8())),
+        }
+    }
+}
+
+pub fn tokenize(s: &str) -> anyhow::Result<Vec<Token>> {
+    Ok(s.split_whitespace()
+        .map(Token::from_str)
+        .collect::<Result<Vec<Token>, _>>()?)
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum Expr {
+    Bool(bool),
+    Int(Rc<BigInt>),
+    String(Rc<String>),
+    Var(usize),
+    Un(UnOp, Rc<Expr>),
+    Bin(BinOp, Rc<Expr>, Rc<Expr>),
+    If(Rc<Expr>, Rc<Expr>, Rc<Expr>),
+    Lambda(usize, Rc<Expr>),
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expr::Bool(v) => write!(f, "{}", if *v { "T" } else { "F" }),
+            Expr::Int(n) => write!(f, "{n}"),
+            Expr::String(s) => write!(f, "{s:?}"),
+            Expr::Var(v) => write!(f, "v{v}"),
+            Expr::Un(op, e) => write!(f, "({op} {e})"),
+            Expr::Bin(op, l, r) => match op {
+                BinOp::App => {
+                    write!(f, "({l} {r})")
+                }
+                _ => {
+                    write!(f, "({op} {l} {r})")
+                }
+            },
+            Expr::If(cond, th, el) => write!(f, "if {cond} then {th} else {el}"),
+            Expr::Lambda(v, e) => write!(f, "(λv{v}. {e})"),
+        }
+    }
+}
+
+impl Expr {
+    pub fn is_nf(&self) -> bool {
+        match self {
+            Expr::Bool(_) | Expr::Int(_) | Expr::String(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn parse_tokens(tokens: &[Token]) -> anyhow::Result<Expr> {
+        Ok(parse::parse_tokens(tokens)?)
+    }
+
+    pub fn to_tokens(&self) -> Vec<Token> {
+        let mut ret = vec![];
+        self.push_tokens(&mut ret);
+        ret
+    }
+
+    /// Appends the tokens in pre-order, without allocating at every level.
+    fn push_tokens(&self, out: &mut Vec<Token>) {
+        match self {
+            Expr::Bool(b) => out.push(Token::Bool(*b)),
+            Expr::Int(n) => out.push(Token::Int(n.as_ref().clone())),
+            Expr::String(s) => out.push(Token::String(s.as_ref().clone())),
+            Expr::Var(v) => out.push(Token::Var(*v)),
+            Expr::Un(op, e) => {
+                out.push(Token::Un(*op));
+                e.push_tokens(out);
+            }
+            Expr::Bin(op, l, r) => {
+                out.push(Token::Bin(*op));
+                l.push_tokens(out);
+                r.push_tokens(out);
+            }
+            Expr::If(cond, th, el) => {
+                out.push(Token::If);
+                cond.push_tokens(out);
+                th.push_tokens(out);
+                el.push_tokens(out);
+            }
+            Expr::Lambda(v, e) => {
+                out.push(Token::Lambda(*v));
+                e.push_tokens(out);
+            }
+        }
+    }
+
+    pub fn encoded(&self) -> ExprEncoded {
+        ExprEncoded(self)
+    }
+
+    /// Prints the expression in the readable syntax of `parse_surface`.
+    pub fn surface(&self) -> ExprSurface<'_> {
+        ExprSurface(self)
+    }
+}
+
+impl FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Expr> {
+        Ok(parse(s)?.0)
+    }
+}
+
+pub struct ExprEncoded<'a>(&'a Expr);
+
+impl std::fmt::Display for ExprEncoded<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.write_encoded(&mut encode::FmtWriter(f)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::InvalidData => panic!("{e}"),
+            Err(_) => Err(std::fmt::Error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_encoded() {
+        assert_eq!("T", Token::Bool(true).encoded().to_string());
+        assert_eq!("F", Token::Bool(false).encoded().to_string());
+        assert_eq!("I!", Token::Int(0.into()).encoded().to_string());
+        assert_eq!("I\"", Token::Int(1.into()).encoded().to_string());
+        assert_eq!("I/6", Token::Int(1337.into()).encoded().to_string());
+        assert_eq!(
+            "SB%,,/}Q/2,$_",
+            Token::String("Hello World!".into()).encoded().to_string()
+        );
+        assert_eq!("U-", Token::Un(UnOp::Neg).encoded().to_string());
+        assert_eq!("U!", Token::Un(UnOp::Not).encoded().to_string());
+        assert_eq!("U#", Token::Un(UnOp::StrToInt).encoded().to_string());
+        assert_eq!("U$", Token::Un(UnOp::IntToStr).encoded().to_string());
+        assert_eq!("B+", Token::Bin(BinOp::Add).encoded().to_string());
+        assert_eq!("BT", Token::Bin(BinOp::Take).encoded().to_string());
+        assert_eq!("BD", Token::Bin(BinOp::Drop).encoded().to_string());
+        assert_eq!("B$", Token::Bin(BinOp::App).encoded().to_string());
+        assert_eq!("?", Token::If.encoded().to_string());
+        assert_eq!("L#", Token::Lambda(2).encoded().to_string());
+        assert_eq!("v#", Token::Var(2).encoded().to_string());
+        assert_eq!("B~", Token::Bin(BinOp::AppL).encoded().to_string());
+        assert_eq!("B!", Token::Bin(BinOp::AppV).encoded().to_string());
+        assert_eq!(
+            Token::String("caf\u{e9}".into()).try_encode(),
+            Err(Base94Error::UnencodableChars(vec![(3, '\u{e9}')]))
+        );
+        assert_eq!(
+            Token::Int((-1).into()).try_encode(),
+            Err(Base94Error::NegativeNumber((-1).into()))
+        );
+    }
+
+    #[test]
+    fn expr_encoded() {
+        let eff12 = r#"B$ B$ L" B$ L# B$ v" B$ v# v# L# B$ v" B$ v# v# L$ L% B$ B$ L" L# ? B< v" v# v" v# v% B+ I" ? B> v% I# B$ B$ B$ L" B$ L# B$ v" B$ v# v# L# B$ v" B$ v# v# L& L' L( ? B= v' v% v( B$ B$ v& B+ v' I" ? B> B$ v$ v' B- v' I" ? B= B% v% v' I! B* B/ v( B$ v$ v' B- B$ v$ v' I" v( v( I# v% v% I"Ndb"#;
+        let expr: Expr = eff12.parse().unwrap();
+        assert_eq!(eff12, expr.encoded().to_string());
+    }
+}
@@ -0,0 +1,589 @@
+//! Streaming parser for ICFP programs.
+//!
+//! Tokens are read incrementally from a `BufRead`, and the expression is built
+//! with an explicit stack, so neither the size of the input nor the nesting
+//! depth of the program is bounded by memory for tokens or the Rust stack.
+
+use std::{borrow::Borrow, io::BufRead, ops::Range, rc::Rc, str::FromStr};
+
+use super::{BinOp, Expr, Token, TokenError, TokenErrorKind, UnOp};
+
+/// A position in the source. Lines and columns start at 1, and columns count
+/// bytes.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+}
+
+impl Position {
+    pub(super) fn advance(&mut self, b: u8) {
+        self.offset += 1;
+        if b == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+    }
+}
+
+/// A range in the source. `end` is exclusive.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Span {
+    pub start: Position,
+    pub end: Position,
+}
+
+impl Span {
+    /// The part of the span at the byte range. Tokens have no whitespace, so
+    /// they are on a single line.
+    fn sub(&self, range: Range<usize>) -> Span {
+        let at = |i: usize| Position {
+            offset: self.start.offset + i,
+            line: self.start.line,
+            column: self.start.column + i,
+        };
+        Span {
+            start: at(range.start),
+            end: at(range.end),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseErrorKind {
+    InvalidToken(TokenError),
+    UnexpectedEnd,
+    ExtraToken,
+    Io(std::io::Error),
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// The index of the token where the error was found.
+    pub token: usize,
+    /// The offending characters, if parsed from text.
+    pub span: Option<Span>,
+}
+
+impl ParseError {
+    /// Shows the error with the line of `source` it was found in. `source`
+    /// must be the text that was parsed.
+    pub fn diagnostic<'a>(&'a self, source: &'a str) -> Diagnostic<'a> {
+        Diagnostic {
+            error: self,
+            source,
+        }
+    }
+
+    fn message(&self) -> String {
+        match &self.kind {
+            ParseErrorKind::InvalidToken(e) => e.to_string(),
+            ParseErrorKind::UnexpectedEnd => "unexpected end of input".to_string(),
+            ParseErrorKind::ExtraToken => "extra token after the expression".to_string(),
+            ParseErrorKind::Io(e) => format!("read error: {e}"),
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at token {}", self.message(), self.token)?;
+        if let Some(span) = self.span {
+            write!(
+                f,
+                " (line {}, column {})",
+                span.start.line, span.start.column
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Lines are cut to this many bytes on each side of the error, as programs
+/// are usually a single long line.
+const CONTEXT: usize = 40;
+
+/// A parse error rendered with the offending characters marked, e.g.
+///
+/// ```text
+/// error: unknown operator `x` in `Bx`
+///  --> line 1, column 5
+///   |
+/// 1 | B$ Bx I"
+///   |     ^
+/// ```
+pub struct Diagnostic<'a> {
+    error: &'a ParseError,
+    source: &'a str,
+}
+
+impl std::fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "error: {}", self.error.message())?;
+        let Some(span) = self.error.span else {
+            return write!(f, " --> token {}", self.error.token);
+        };
+        writeln!(
+            f,
+            " --> line {}, column {}",
+            span.start.line, span.start.column
+        )?;
+
+        let line = self.source.lines().nth(span.start.line - 1).unwrap_or("");
+        let col = (span.start.column - 1).min(line.len());
+        let width = (span.end.offset - span.start.offset).max(1);
+        let mut from = col.saturating_sub(CONTEXT);
+        while !line.is_char_boundary(from) {
+            from -= 1;
+        }
+        let mut to = (col + width + CONTEXT).min(line.len());
+        while !line.is_char_boundary(to) {
+            to += 1;
+        }
+        let prefix = if from > 0 { "..." } else { "" };
+        let suffix = if to < line.len() { "..." } else { "" };
+
+        let number = span.start.line.to_string();
+        let margin = " ".repeat(number.len());
+        writeln!(f, "{margin} |")?;
+        writeln!(f, "{number} | {prefix}{}{suffix}", &line[from..to])?;
+        write!(
+            f,
+            "{margin} | {}{}",
+            " ".repeat(prefix.len() + col - from),
+            "^".repeat(width)
+        )
+    }
+}
+
+/// The spans of the nodes of a parsed expression. Each node is one token, so
+/// nodes are numbered in pre-order, like the tokens.
+#[derive(Clone, Debug, Default)]
+pub struct SourceMap {
+    spans: Vec<Span>,
+}
+
+impl SourceMap {
+    /// The span of the `node`-th node in pre-order.
+    pub fn span(&self, node: usize) -> Option<Span> {
+        self.spans.get(node).copied()
+    }
+
+    /// The byte offset of the `node`-th node in pre-order.
+    pub fn offset(&self, node: usize) -> Option<usize> {
+        self.span(node).map(|span| span.start.offset)
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+}
+
+/// Reads whitespace-separated tokens with their spans.
+pub struct TokenReader<R> {
+    reader: R,
+    position: Position,
+    index: usize,
+    buf: Vec<u8>,
+}
+
+impl<R: BufRead> TokenReader<R> {
+    pub fn new(reader: R) -> Self {
+        TokenReader {
+            reader,
+            position: Position::default(),
+            index: 0,
+            buf: vec![],
+        }
+    }
+
+    /// Reads the bytes of the next token into `buf`, and returns its span.
+    fn read_token(&mut self) -> std::io::Result<Option<Span>> {
+        self.buf.clear();
+        let mut start = None;
+        loop {
+            let chunk = self.reader.fill_buf()?;
+            if chunk.is_empty() {
+                break;
+            }
+            let mut used = 0;
+            let mut done = false;
+            for &b in chunk {
+                if b.is_ascii_whitespace() {
+                    if start.is_some() {
+                        done = true;
+                        break;
+                    }
+                } else {
+                    start.get_or_insert(self.position);
+                    self.buf.push(b);
+                }
+                self.position.advance(b);
+                used += 1;
+            }
+            self.reader.consume(used);
+            if done {
+                break;
+            }
+        }
+        Ok(start.map(|start| Span {
+            start,
+            end: self.position,
+        }))
+    }
+
+    /// Decodes `buf`. On errors, also returns the byte range of the offending
+    /// characters in `buf`.
+    fn decode(&self) -> Result<Token, (TokenError, Range<usize>)> {
+        match std::str::from_utf8(&self.buf) {
+            Ok(s) => Token::from_str(s).map_err(|e| {
+                let range = e.range.clone();
+                (e, range)
+            }),
+            Err(e) => {
+                // The invalid bytes are shown as U+FFFD.
+                let start = e.valid_up_to();
+                let len = e.error_len().unwrap_or(self.buf.len() - start);
+                let mut token = String::from_utf8_lossy(&self.buf[..start]).into_owned();
+                token.push(char::REPLACEMENT_CHARACTER);
+                let error = TokenError {
+                    kind: TokenErrorKind::InvalidCharacter,
+                    range: start..token.len(),
+                    token,
+                };
+                Err((error, start..start + len))
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for TokenReader<R> {
+    type Item = Result<(Span, Token), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let span = match self.read_token() {
+            Ok(span) => span?,
+            Err(e) => {
+                return Some(Err(ParseError {
+                    kind: ParseErrorKind::Io(e),
+                    token: self.index,
+                    span: None,
+                }))
+            }
+        };
+        let token = self.decode().map_err(|(e, range)| ParseError {
+            kind: ParseErrorKind::InvalidToken(e),
+            token: self.index,
+            span: Some(span.sub(range)),
+        });
+        self.index += 1;
+        Some(token.map(|token| (span, token)))
+    }
+}
+
+/// Parses an expression from text.
+pub fn parse(s: &str) -> Result<(Expr, SourceMap), ParseError> {
+    parse_reader(s.as_bytes())
+}
+
+/// Parses an expression from a reader, e.g. a `BufReader<File>`.
+pub fn parse_reader(reader: impl BufRead) -> Result<(Expr, SourceMap), ParseError> {
+    let mut source_map = SourceMap::default();
+    let expr = build(
+        &mut Tree,
+        TokenReader::new(reader).map(|token| {
+            let (span, token) = token?;
+            source_map.spans.push(span);
+            Ok((Some(span), token))
+        }),
+    )?;
+    Ok((expr, source_map))
+}
+
+/// Parses an expression from tokens without spans.
+pub(super) fn parse_tokens(tokens: &[Token]) -> Result<Expr, ParseError> {
+    build(&mut Tree, tokens.iter().map(|token| Ok((None, token))))
+}
+
+/// Constructs the nodes of a parsed expression, so that the parser can build
+/// other representations than `Expr`.
+pub(super) trait Builder {
+    type Node;
+
+    /// A literal or a variable.
+    fn leaf(&mut self, token: &Token) -> Self::Node;
+    fn un(&mut self, op: UnOp, e: Self::Node) -> Self::Node;
+    fn bin(&mut self, op: BinOp, l: Self::Node, r: Self::Node) -> Self::Node;
+    fn if_(&mut self, cond: Self::Node, th: Self::Node, el: Self::Node) -> Self::Node;
+    fn lambda(&mut self, v: usize, body: Self::Node) -> Self::Node;
+}
+
+/// Builds `Expr` trees.
+struct Tree;
+
+impl Builder for Tree {
+    type Node = Expr;
+
+    fn leaf(&mut self, token: &Token) -> Expr {
+        match token {
+            Token::Bool(b) => Expr::Bool(*b),
+            Token::Int(n) => Expr::Int(n.clone().into()),
+            Token::String(s) => Expr::String(s.clone().into()),
+            Token::Var(v) => Expr::Var(*v),
+            _ => unreachable!("not a leaf: {token:?}"),
+        }
+    }
+
+    fn un(&mut self, op: UnOp, e: Expr) -> Expr {
+        Expr::Un(op, Rc::new(e))
+    }
+
+    fn bin(&mut self, op: BinOp, l: Expr, r: Expr) -> Expr {
+        Expr::Bin(op, Rc::new(l), Rc::new(r))
+    }
+
+    fn if_(&mut self, cond: Expr, th: Expr, el: Expr) -> Expr {
+        Expr::If(Rc::new(cond), Rc::new(th), Rc::new(el))
+    }
+
+    fn lambda(&mut self, v: usize, body: Expr) -> Expr {
+        Expr::Lambda(v, Rc::new(body))
+    }
+}
+
+/// An operator waiting for its operands.
+enum Pending<N> {
+    Un(UnOp),
+    Bin(BinOp, Option<N>),
+    If(Vec<N>),
+    Lambda(usize),
+}
+
+pub(super) fn build<B: Builder, T: Borrow<Token>>(
+    builder: &mut B,
+    mut tokens: impl Iterator<Item = Result<(Option<Span>, T), ParseError>>,
+) -> Result<B::Node, ParseError> {
+    let mut stack: Vec<Pending<B::Node>> = vec![];
+    let mut count = 0;
+    let mut last: Option<Span> = None;
+
+    loop {
+        let Some(token) = tokens.next() else {
+            // Points just after the last token.
+            return Err(ParseError {
+                kind: ParseErrorKind::UnexpectedEnd,
+                token: count,
+                span: last.map(|span| Span {
+                    start: span.end,
+                    end: span.end,
+                }),
+            });
+        };
+        let (span, token) = token?;
+        last = span;
+        count += 1;
+        let mut e = match token.borrow() {
+            token @ (Token::Bool(_) | Token::Int(_) | Token::String(_) | Token::Var(_)) => {
+                builder.leaf(token)
+            }
+            Token::Un(op) => {
+                stack.push(Pending::Un(*op));
+                continue;
+            }
+            Token::Bin(op) => {
+                stack.push(Pending::Bin(*op, None));
+                continue;
+            }
+            Token::If => {
+                stack.push(Pending::If(vec![]));
+                continue;
+            }
+            Token::Lambda(v) => {
+                stack.push(Pending::Lambda(*v));
+                continue;
+            }
+        };
+
+        // Completes the operators that have all of their operands.
+        loop {
+            e = match stack.pop() {
+                None => {
+                    return match tokens.next() {
+                        None => Ok(e),
+                        Some(Err(e)) => Err(e),
+                        Some(Ok((span, _))) => Err(ParseError {
+                            kind: ParseErrorKind::ExtraToken,
+                            token: count,
+                            span,
+                        }),
+                    };
+                }
+                Some(Pending::Un(op)) => builder.un(op, e),
+                Some(Pending::Bin(op, None)) => {
+                    stack.push(Pending::Bin(op, Some(e)));
+                    break;
+                }
+                Some(Pending::Bin(op, Some(l))) => builder.bin(op, l, e),
+                Some(Pending::If(mut operands)) => {
+                    operands.push(e);
+                    if operands.len() < 3 {
+                        stack.push(Pending::If(operands));
+                        break;
+                    }
+                    let el = operands.pop().unwrap();
+                    let th = operands.pop().unwrap();
+                    let cond = operands.pop().unwrap();
+                    builder.if_(cond, th, el)
+                }
+                Some(Pending::Lambda(v)) => builder.lambda(v, e),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets() {
+        let (expr, source_map) = parse("B$  L\" v\"\n I\"").unwrap();
+        assert_eq!(expr, "B$ L\" v\" I\"".parse().unwrap());
+        let offsets: Vec<_> = (0..source_map.len())
+            .map(|i| source_map.offset(i).unwrap())
+            .collect();
+        assert_eq!(offsets, vec![0, 4, 7, 11]);
+        let span = source_map.span(3).unwrap();
+        assert_eq!((span.start.line, span.start.column), (2, 2));
+        assert_eq!((span.end.line, span.end.column), (2, 4));
+    }
+
+    #[test]
+    fn small_buffer() {
+        let program = r#"B$ B$ L" B$ L# B$ v" B$ v# v# L# B$ v" B$ v# v# L" L# ? B= v# I! I" B$ L$ B+ B$ v" v$ B$ v" v$ B- v# I" I%"#;
+        let reader = std::io::BufReader::with_capacity(3, program.as_bytes());
+        let (expr, source_map) = parse_reader(reader).unwrap();
+        assert_eq!(expr.encoded().to_string(), program);
+        assert_eq!(source_map.offset(2), Some(6));
+    }
+
+    #[test]
+    fn deep() {
+        let depth = 1_000_000;
+        let program = "U- ".repeat(depth) + "I\"";
+        let (mut expr, _) = parse(&program).unwrap();
+
+        // Dropping such an expression recursively overflows the stack, so it
+        // is taken apart here.
+        let mut n = 0;
+        while let Expr::Un(UnOp::Neg, e) = expr {
+            expr = Rc::try_unwrap(e).unwrap();
+            n += 1;
+        }
+        assert_eq!(n, depth);
+        assert_eq!(expr, Expr::Int(num_bigint::BigInt::from(1).into()));
+    }
+
+    #[test]
+    fn errors() {
+        let err = parse(r#"B$ L" v""#).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::UnexpectedEnd));
+        assert_eq!(
+            err.to_string(),
+            "unexpected end of input at token 3 (line 1, column 9)"
+        );
+
+        let err = parse(r#"I" I""#).unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::ExtraToken));
+        assert_eq!(
+            err.to_string(),
+            "extra token after the expression at token 1 (line 1, column 4)"
+        );
+
+        let err = parse("").unwrap_err();
+        assert!(matches!(err.kind, ParseErrorKind::UnexpectedEnd));
+        assert_eq!(err.to_string(), "unexpected end of input at token 0");
+    }
+
+    #[test]
+    fn invalid_tokens() {
+        for (token, kind, chars, column) in [
+            ("Bx", TokenErrorKind::UnknownOperator, "x", 2),
+            ("B", TokenErrorKind::UnknownOperator, "", 2),
+            ("U-x", TokenErrorKind::TrailingCharacters, "x", 3),
+            ("TT", TokenErrorKind::TrailingCharacters, "T", 2),
+            ("x", TokenErrorKind::UnknownIndicator, "x", 1),
+            ("I!é", TokenErrorKind::InvalidDigit, "é", 3),
+            ("S!é", TokenErrorKind::InvalidCharacter, "é", 3),
+            // 94^11 does not fit in 64 bits.
+            (
+                "v~~~~~~~~~~~",
+                TokenErrorKind::VariableOutOfRange,
+                "~~~~~~~~~~~",
+                2,
+            ),
+        ] {
+            let err = parse(&format!("B$ {token} I\"")).unwrap_err();
+            let ParseErrorKind::InvalidToken(e) = &err.kind else {
+                panic!("{token}: {err}");
+            };
+            assert_eq!(e.kind, kind, "{token}");
+            assert_eq!(e.chars(), chars, "{token}");
+            assert_eq!(err.token, 1, "{token}");
+            assert_eq!(err.span.unwrap().start.column, 3 + column, "{token}");
+        }
+
+        let err = parse_reader(&b"B$ S!\xff I\""[..]).unwrap_err();
+        let ParseErrorKind::InvalidToken(e) = &err.kind else {
+            panic!("{err}");
+        };
+        assert_eq!(e.kind, TokenErrorKind::InvalidCharacter);
+        assert_eq!(e.chars(), "\u{fffd}");
+        assert_eq!(err.span.unwrap().start.column, 6);
+    }
+
+    #[test]
+    fn diagnostic() {
+        let source = "B$ L\" v\"\nB$ Bx I\"";
+        let err = parse(source).unwrap_err();
+        assert_eq!(
+            err.diagnostic(source).to_string(),
+            [
+                "error: unknown operator `x` in `Bx`",
+                " --> line 2, column 5",
+                "  |",
+                "2 | B$ Bx I\"",
+                "  |     ^",
+            ]
+            .join("\n")
+        );
+
+        // Long lines are cut around the error.
+        let source = "B$ ".repeat(30) + "Bx" + &" I\"".repeat(31);
+        let err = parse(&source).unwrap_err();
+        let diagnostic = err.diagnostic(&source).to_string();
+        let lines: Vec<&str> = diagnostic.lines().collect();
+        assert_eq!(lines[3], format!("1 | ...{}...", &source[51..132]));
+        assert_eq!(lines[4], format!("  | {}^", " ".repeat(43)));
+    }
+}
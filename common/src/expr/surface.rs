@@ -0,0 +1,722 @@
+//! A readable syntax for ICFP expressions.
+//!
+//! `Expr::surface` prints an expression in this syntax, and `parse_surface`
+//! parses it back into exactly the same expression, so decoded programs can be
+//! edited by hand and encoded again.
+//!
+//! ```text
+//! expr := λx y. expr                    lambdas (also `\x y. expr`)
+//!       | let x = expr in expr          (λx. expr) expr
+//!       | if expr then expr else expr
+//!       | expr op expr                  binary operators, see below
+//!       | f arg | f ~arg | f !arg       B$, B~ (call-by-need), B! (call-by-value)
+//!       | neg a | not a | to_int a | to_str a | take n s | drop n s
+//!       | x | 42 | -42 | "str" | true | false | (expr)
+//! ```
+//!
+//! Binary operators from the loosest: `||`, `&&`, `== < >`, `++` (concat),
+//! `+ -`, `* / %`. All of them are left-associative, and application binds
+//! tighter than any of them. Arguments must be atoms, so a negative literal is
+//! written as `f (-1)`.
+//!
+//! Variables are written `v12` for the variable 12. Other names are given
+//! numbers larger than any `vN` in the source, so `λx. x` and `λv1. v1` both
+//! parse, but only the latter keeps its number. Strings use `\"`, `\\`, `\n`
+//! and `\u{7f}` escapes. `#` starts a comment.
+
+use std::collections::HashMap;
+
+use num_bigint::BigInt;
+
+use super::{BinOp, Expr, Position, UnOp};
+
+/// Binary operators by precedence level, from the loosest.
+const LEVELS: [&[(&str, BinOp)]; 6] = [
+    &[("||", BinOp::Or)],
+    &[("&&", BinOp::And)],
+    &[("==", BinOp::Eq), ("<", BinOp::Lt), (">", BinOp::Gt)],
+    &[("++", BinOp::Concat)],
+    &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    &[("*", BinOp::Mul), ("/", BinOp::Div), ("%", BinOp::Mod)],
+];
+
+/// The level of lambdas, lets and ifs, which extend as far right as possible.
+const LEVEL_BINDER: usize = 0;
+/// The level of applications, including the keyword operators.
+const LEVEL_APP: usize = LEVELS.len() + 1;
+const LEVEL_ATOM: usize = LEVEL_APP + 1;
+
+const UN_OPS: [(&str, UnOp); 4] = [
+    ("neg", UnOp::Neg),
+    ("not", UnOp::Not),
+    ("to_int", UnOp::StrToInt),
+    ("to_str", UnOp::IntToStr),
+];
+
+const KEYWORDS: [&str; 13] = [
+    "let", "in", "if", "then", "else", "true", "false", "neg", "not", "to_int", "to_str", "take",
+    "drop",
+];
+
+fn binary_level(op: BinOp) -> Option<(usize, &'static str)> {
+    LEVELS
+        .iter()
+        .enumerate()
+        .find_map(|(i, ops)| ops.iter().find(|(_, o)| *o == op).map(|(s, _)| (i + 1, *s)))
+}
+
+pub struct ExprSurface<'a>(pub(super) &'a Expr);
+
+impl std::fmt::Display for ExprSurface<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_expr(f, self.0, LEVEL_BINDER)
+    }
+}
+
+fn level(e: &Expr) -> usize {
+    match e {
+        Expr::Lambda(_, _) | Expr::If(_, _, _) => LEVEL_BINDER,
+        Expr::Bin(BinOp::App, l, _) if matches!(l.as_ref(), Expr::Lambda(_, _)) => LEVEL_BINDER,
+        Expr::Bin(op, _, _) => binary_level(*op).map_or(LEVEL_APP, |(level, _)| level),
+        Expr::Un(_, _) => LEVEL_APP,
+        // Negative literals cannot be arguments.
+        Expr::Int(n) if n.sign() == num_bigint::Sign::Minus => LEVEL_APP,
+        _ => LEVEL_ATOM,
+    }
+}
+
+fn write_expr(f: &mut std::fmt::Formatter<'_>, e: &Expr, min: usize) -> std::fmt::Result {
+    if level(e) < min {
+        write!(f, "(")?;
+        write_expr(f, e, LEVEL_BINDER)?;
+        return write!(f, ")");
+    }
+    match e {
+        Expr::Bool(b) => write!(f, "{b}"),
+        Expr::Int(n) => write!(f, "{n}"),
+        Expr::String(s) => write_string(f, s),
+        Expr::Var(v) => write!(f, "v{v}"),
+        Expr::Lambda(v, body) => {
+            write!(f, "λv{v}")?;
+            let mut body = body;
+            while let Expr::Lambda(v, e) = body.as_ref() {
+                write!(f, " v{v}")?;
+                body = e;
+            }
+            write!(f, ". ")?;
+            write_expr(f, body, LEVEL_BINDER)
+        }
+        Expr::If(cond, th, el) => {
+            write!(f, "if ")?;
+            write_expr(f, cond, LEVEL_BINDER)?;
+            write!(f, " then ")?;
+            write_expr(f, th, LEVEL_BINDER)?;
+            write!(f, " else ")?;
+            write_expr(f, el, LEVEL_BINDER)
+        }
+        Expr::Un(op, e) => {
+            let (name, _) = UN_OPS.iter().find(|(_, o)| o == op).unwrap();
+            write!(f, "{name} ")?;
+            write_expr(f, e, LEVEL_ATOM)
+        }
+        Expr::Bin(op, l, r) => {
+            if let (BinOp::App, Expr::Lambda(v, body)) = (op, l.as_ref()) {
+                write!(f, "let v{v} = ")?;
+                write_expr(f, r, LEVEL_BINDER)?;
+                write!(f, " in ")?;
+                return write_expr(f, body, LEVEL_BINDER);
+            }
+            let (head, prefix) = match op {
+                BinOp::App => (LEVEL_APP, ""),
+                BinOp::AppL => (LEVEL_APP, "~"),
+                BinOp::AppV => (LEVEL_APP, "!"),
+                BinOp::Take => return write_keyword(f, "take", l, r),
+                BinOp::Drop => return write_keyword(f, "drop", l, r),
+                _ => {
+                    let (level, symbol) = binary_level(*op).unwrap();
+                    write_expr(f, l, level)?;
+                    write!(f, " {symbol} ")?;
+                    return write_expr(f, r, level + 1);
+                }
+            };
+            write_expr(f, l, head)?;
+            write!(f, " {prefix}")?;
+            write_expr(f, r, LEVEL_ATOM)
+        }
+    }
+}
+
+fn write_keyword(
+    f: &mut std::fmt::Formatter<'_>,
+    name: &str,
+    l: &Expr,
+    r: &Expr,
+) -> std::fmt::Result {
+    write!(f, "{name} ")?;
+    write_expr(f, l, LEVEL_ATOM)?;
+    write!(f, " ")?;
+    write_expr(f, r, LEVEL_ATOM)
+}
+
+fn write_string(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            c if c.is_control() => write!(f, "\\u{{{:x}}}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct SyntaxError {
+    pub message: String,
+    pub position: Position,
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} at line {}, column {}",
+            self.message, self.position.line, self.position.column
+        )
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+enum Tok {
+    Ident(String),
+    Int(BigInt),
+    Str(String),
+    Symbol(&'static str),
+    End,
+}
+
+impl std::fmt::Display for Tok {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tok::Ident(s) => write!(f, "`{s}`"),
+            Tok::Int(n) => write!(f, "`{n}`"),
+            Tok::Str(s) => write!(f, "{s:?}"),
+            Tok::Symbol(s) => write!(f, "`{s}`"),
+            Tok::End => write!(f, "end of input"),
+        }
+    }
+}
+
+/// Longer symbols first, so that `==` is not read as `=`.
+const SYMBOLS: [&str; 19] = [
+    "||", "&&", "==", "++", "λ", "\\", ".", "(", ")", "=", "<", ">", "+", "-", "*", "/", "%", "~",
+    "!",
+];
+
+struct Lexer<'a> {
+    rest: &'a str,
+    position: Position,
+}
+
+impl Lexer<'_> {
+    fn error(&self, message: String) -> SyntaxError {
+        SyntaxError {
+            message,
+            position: self.position,
+        }
+    }
+
+    fn bump(&mut self, len: usize) -> &str {
+        let (s, rest) = self.rest.split_at(len);
+        for b in s.bytes() {
+            self.position.advance(b);
+        }
+        self.rest = rest;
+        s
+    }
+
+    fn skip_space(&mut self) {
+        loop {
+            let trimmed = self.rest.trim_start();
+            self.bump(self.rest.len() - trimmed.len());
+            if !self.rest.starts_with('#') {
+                return;
+            }
+            self.bump(self.rest.find('\n').unwrap_or(self.rest.len()));
+        }
+    }
+
+    fn next(&mut self) -> Result<(Position, Tok), SyntaxError> {
+        self.skip_space();
+        let start = self.position;
+        let Some(c) = self.rest.chars().next() else {
+            return Ok((start, Tok::End));
+        };
+        let len = |pred: fn(char) -> bool| self.rest.find(|c| !pred(c)).unwrap_or(self.rest.len());
+        let tok = if c.is_ascii_digit() {
+            let n = len(|c| c.is_ascii_digit());
+            Tok::Int(self.bump(n).parse().unwrap())
+        } else if c.is_alphabetic() || c == '_' {
+            // `λ` is alphabetic, but is not part of names.
+            if c == 'λ' {
+                self.bump(c.len_utf8());
+                return Ok((start, Tok::Symbol("λ")));
+            }
+            let n = len(|c| (c.is_alphanumeric() && c != 'λ') || c == '_' || c == '\'');
+            Tok::Ident(self.bump(n).to_string())
+        } else if c == '"' {
+            self.bump(1);
+            Tok::Str(self.string()?)
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| self.rest.starts_with(**s)) {
+            self.bump(symbol.len());
+            Tok::Symbol(symbol)
+        } else {
+            return Err(self.error(format!("unexpected character {c:?}")));
+        };
+        Ok((start, tok))
+    }
+
+    /// Reads a string after the opening quote.
+    fn string(&mut self) -> Result<String, SyntaxError> {
+        let mut s = String::new();
+        loop {
+            let Some(c) = self.rest.chars().next() else {
+                return Err(self.error("unterminated string".to_string()));
+            };
+            let escape = self.position;
+            self.bump(c.len_utf8());
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let invalid = || SyntaxError {
+                        message: "invalid escape sequence".to_string(),
+                        position: escape,
+                    };
+                    let c = self.rest.chars().next().ok_or_else(invalid)?;
+                    self.bump(c.len_utf8());
+                    match c {
+                        '"' | '\\' => s.push(c),
+                        'n' => s.push('\n'),
+                        'u' if self.rest.starts_with('{') => {
+                            let end = self.rest.find('}').ok_or_else(invalid)?;
+                            let code = u32::from_str_radix(&self.rest[1..end], 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(invalid)?;
+                            self.bump(end + 1);
+                            s.push(code);
+                        }
+                        _ => return Err(invalid()),
+                    }
+                }
+                c => s.push(c),
+            }
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Position, Tok)>,
+    pos: usize,
+    vars: HashMap<String, usize>,
+    next_var: usize,
+}
+
+/// Parses an expression in the syntax described in the module documentation.
+pub fn parse_surface(s: &str) -> Result<Expr, SyntaxError> {
+    let mut lexer = Lexer {
+        rest: s,
+        position: Position::default(),
+    };
+    let mut tokens = vec![];
+    loop {
+        let (position, tok) = lexer.next()?;
+        tokens.push((position, tok.clone()));
+        if tok == Tok::End {
+            break;
+        }
+    }
+
+    // Names are numbered after the largest `vN`, so that they do not clash.
+    let next_var = tokens
+        .iter()
+        .filter_map(|(_, tok)| match tok {
+            Tok::Ident(name) => var_number(name),
+            _ => None,
+        })
+        .max()
+        .map_or(1, |v| v + 1);
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        vars: HashMap::new(),
+        next_var,
+    };
+    let e = parser.expr()?;
+    parser.expect_end()?;
+    Ok(e)
+}
+
+/// The number of a variable written as `vN`.
+fn var_number(name: &str) -> Option<usize> {
+    let digits = name.strip_prefix('v')?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+impl Parser {
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].1
+    }
+
+    fn advance(&mut self) -> Tok {
+        let tok = self.tokens[self.pos].1.clone();
+        if tok != Tok::End {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    fn error(&self, expected: &str) -> SyntaxError {
+        let (position, tok) = &self.tokens[self.pos];
+        SyntaxError {
+            message: format!("expected {expected}, found {tok}"),
+            position: *position,
+        }
+    }
+
+    fn is_symbol(&self, symbol: &str) -> bool {
+        matches!(self.peek(), Tok::Symbol(s) if *s == symbol)
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Tok::Ident(s) if s == keyword)
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), SyntaxError> {
+        if !self.is_symbol(symbol) {
+            return Err(self.error(&format!("`{symbol}`")));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), SyntaxError> {
+        if !self.is_keyword(keyword) {
+            return Err(self.error(&format!("`{keyword}`")));
+        }
+        self.advance();
+        Ok(())
+    }
+
+    fn expect_end(&self) -> Result<(), SyntaxError> {
+        match self.peek() {
+            Tok::End => Ok(()),
+            _ => Err(self.error("end of input")),
+        }
+    }
+
+    fn var(&mut self) -> Result<usize, SyntaxError> {
+        match self.peek() {
+            Tok::Ident(name) if !KEYWORDS.contains(&name.as_str()) => {
+                let name = name.clone();
+                self.advance();
+                if let Some(v) = var_number(&name) {
+                    return Ok(v);
+                }
+                let next_var = &mut self.next_var;
+                Ok(*self.vars.entry(name).or_insert_with(|| {
+                    *next_var += 1;
+                    *next_var - 1
+                }))
+            }
+            _ => Err(self.error("a variable")),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, SyntaxError> {
+        if self.is_symbol("λ") || self.is_symbol("\\") {
+            self.advance();
+            let mut vars = vec![self.var()?];
+            while !self.is_symbol(".") {
+                vars.push(self.var()?);
+            }
+            self.advance();
+            let body = self.expr()?;
+            return Ok(vars
+                .into_iter()
+                .rev()
+                .fold(body, |body, v| Expr::Lambda(v, body.into())));
+        }
+        if self.is_keyword("let") {
+            self.advance();
+            let v = self.var()?;
+            self.expect_symbol("=")?;
+            let value = self.expr()?;
+            self.expect_keyword("in")?;
+            let body = self.expr()?;
+            return Ok(Expr::Bin(
+                BinOp::App,
+                Expr::Lambda(v, body.into()).into(),
+                value.into(),
+            ));
+        }
+        if self.is_keyword("if") {
+            self.advance();
+            let cond = self.expr()?;
+            self.expect_keyword("then")?;
+            let th = self.expr()?;
+            self.expect_keyword("else")?;
+            let el = self.expr()?;
+            return Ok(Expr::If(cond.into(), th.into(), el.into()));
+        }
+        self.binary(1)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, SyntaxError> {
+        if level > LEVELS.len() {
+            return self.app();
+        }
+        let mut l = self.binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Tok::Symbol(s) => LEVELS[level - 1]
+                    .iter()
+                    .find(|(symbol, _)| symbol == s)
+                    .map(|(_, op)| *op),
+                _ => None,
+            };
+            let Some(op) = op else {
+                return Ok(l);
+            };
+            self.advance();
+            let r = self.binary(level + 1)?;
+            l = Expr::Bin(op, l.into(), r.into());
+        }
+    }
+
+    fn app(&mut self) -> Result<Expr, SyntaxError> {
+        let mut f = self.head()?;
+        loop {
+            let op = if self.is_symbol("~") {
+                BinOp::AppL
+            } else if self.is_symbol("!") {
+                BinOp::AppV
+            } else if self.starts_atom() {
+                BinOp::App
+            } else {
+                return Ok(f);
+            };
+            if op != BinOp::App {
+                self.advance();
+            }
+            let arg = self.atom()?;
+            f = Expr::Bin(op, f.into(), arg.into());
+        }
+    }
+
+    /// The function of an application: an atom, a keyword operator with its
+    /// operands, or a negative literal.
+    fn head(&mut self) -> Result<Expr, SyntaxError> {
+        if let Tok::Ident(name) = self.peek() {
+            if let Some((_, op)) = UN_OPS.iter().find(|(s, _)| s == name) {
+                let op = *op;
+                self.advance();
+                return Ok(Expr::Un(op, self.atom()?.into()));
+            }
+            let op = match name.as_str() {
+                "take" => Some(BinOp::Take),
+                "drop" => Some(BinOp::Drop),
+                _ => None,
+            };
+            if let Some(op) = op {
+                self.advance();
+                let l = self.atom()?;
+                let r = self.atom()?;
+                return Ok(Expr::Bin(op, l.into(), r.into()));
+            }
+        }
+        if self.is_symbol("-") {
+            if let Tok::Int(n) = &self.tokens[self.pos + 1].1 {
+                let n = -n;
+                self.pos += 2;
+                return Ok(Expr::Int(n.into()));
+            }
+        }
+        self.atom()
+    }
+
+    fn starts_atom(&self) -> bool {
+        match self.peek() {
+            Tok::Ident(name) => {
+                !KEYWORDS.contains(&name.as_str()) || name == "true" || name == "false"
+            }
+            Tok::Int(_) | Tok::Str(_) => true,
+            Tok::Symbol(s) => *s == "(",
+            Tok::End => false,
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, SyntaxError> {
+        if !self.starts_atom() {
+            return Err(self.error("an expression"));
+        }
+        Ok(match self.peek() {
+            Tok::Ident(name) if name == "true" => {
+                self.advance();
+                Expr::Bool(true)
+            }
+            Tok::Ident(name) if name == "false" => {
+                self.advance();
+                Expr::Bool(false)
+            }
+            Tok::Ident(_) => Expr::Var(self.var()?),
+            Tok::Int(_) | Tok::Str(_) => match self.advance() {
+                Tok::Int(n) => Expr::Int(n.into()),
+                Tok::Str(s) => Expr::String(s.into()),
+                _ => unreachable!(),
+            },
+            _ => {
+                self.advance();
+                let e = self.expr()?;
+                self.expect_symbol(")")?;
+                e
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn random_expr(rng: &mut StdRng, size: usize) -> Expr {
+        if size <= 1 {
+            return match rng.gen_range(0..5) {
+                0 => Expr::Bool(rng.gen()),
+                1 => Expr::Int(BigInt::from(rng.gen_range(-3..100)).into()),
+                2 => {
+                    let s: String = (0..rng.gen_range(0..4))
+                        .map(|_| ['a', '"', '\\', '\n', ' ', '~', '\u{7f}'][rng.gen_range(0..7)])
+                        .collect();
+                    Expr::String(s.into())
+                }
+                _ => Expr::Var(rng.gen_range(0..4)),
+            };
+        }
+        match rng.gen_range(0..5) {
+            0 => {
+                let op = UnOp::ALL[rng.gen_range(0..UnOp::ALL.len())];
+                Expr::Un(op, random_expr(rng, size - 1).into())
+            }
+            1 | 2 => {
+                let l = rng.gen_range(1..size);
+                let op = BinOp::ALL[rng.gen_range(0..BinOp::ALL.len())];
+                Expr::Bin(
+                    op,
+                    random_expr(rng, l).into(),
+                    random_expr(rng, size - l).into(),
+                )
+            }
+            3 if size >= 3 => {
+                let c = rng.gen_range(1..size - 1);
+                let t = rng.gen_range(1..size - c);
+                Expr::If(
+                    random_expr(rng, c).into(),
+                    random_expr(rng, t).into(),
+                    random_expr(rng, size - c - t).into(),
+                )
+            }
+            _ => Expr::Lambda(rng.gen_range(0..4), random_expr(rng, size - 1).into()),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..10000 {
+            let size = rng.gen_range(1..20);
+            let e = random_expr(&mut rng, size);
+            let s = e.surface().to_string();
+            assert_eq!(parse_surface(&s), Ok(e), "{s}");
+        }
+    }
+
+    #[test]
+    fn print() {
+        let e: Expr = r#"B$ L# B$ v# I$ L" B+ B* v" I# I""#.parse().unwrap();
+        assert_eq!(e.surface().to_string(), "let v2 = λv1. v1 * 2 + 1 in v2 3");
+        let e: Expr = r#"B- B- I$ I# B- I# I""#.parse().unwrap();
+        assert_eq!(e.surface().to_string(), "3 - 2 - (2 - 1)");
+        let e: Expr = r#"B$ B~ v" U- v# BT I# S#"#.parse().unwrap();
+        assert_eq!(e.surface().to_string(), "v1 ~(neg v2) (take 2 \"c\")");
+    }
+
+    #[test]
+    fn names() {
+        let e = parse_surface(
+            "# The factorial of 5.
+            let fix = λf. (λx. f (x x)) (λx. f (x x)) in
+            fix (\\fact n. if n == 0 then 1 else n * fact (n - 1)) 5",
+        )
+        .unwrap();
+        // The self-application in `fix` is a let too.
+        let (fix, f, x, fact, n) = (1, 2, 3, 4, 5);
+        let expected = format!(
+            "let v{fix} = λv{f}. let v{x} = λv{x}. v{f} (v{x} v{x}) in v{f} (v{x} v{x}) in \
+            v{fix} (λv{fact} v{n}. if v{n} == 0 then 1 else v{n} * v{fact} (v{n} - 1)) 5"
+        );
+        assert_eq!(e.surface().to_string(), expected);
+
+        // Names do not clash with numbered variables.
+        let e = parse_surface("λx v3. x").unwrap();
+        assert_eq!(e.surface().to_string(), "λv4 v3. v4");
+    }
+
+    #[test]
+    fn literals() {
+        let e = parse_surface("f (-5) - -3 ++ \"a\\\"\\u{7f}\"").unwrap();
+        assert_eq!(e.surface().to_string(), "v1 (-5) - -3 ++ \"a\\\"\\u{7f}\"");
+        assert_eq!(
+            parse_surface("x-1").unwrap().surface().to_string(),
+            "v1 - 1"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let err = parse_surface("let x = 1\nin x +").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "expected an expression, found end of input at line 2, column 7"
+        );
+        let err = parse_surface("(λx. x").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "expected `)`, found end of input at line 1, column 8"
+        );
+        let err = parse_surface("f @").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unexpected character '@' at line 1, column 3"
+        );
+        let err = parse_surface("\"\\q\"").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid escape sequence at line 1, column 2"
+        );
+        let err = parse_surface("λin. 1").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "expected a variable, found `in` at line 1, column 3"
+        );
+    }
+}
@@ -0,0 +1,221 @@
+//! Generic traversals of `Expr`, and the analyses built on them.
+//!
+//! Implement `Visitor` to look at every node, or `Folder` to rebuild the
+//! expression, overriding `visit` or `fold` for the nodes of interest and
+//! calling `walk` or `fold_children` for the rest.
+
+use std::collections::BTreeSet;
+
+use num_bigint::BigInt;
+
+use super::Expr;
+
+pub trait Visitor {
+    /// Visits `e`. The default visits the children in order.
+    fn visit(&mut self, e: &Expr) {
+        walk(self, e);
+    }
+}
+
+/// Visits the children of `e`.
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, e: &Expr) {
+    for child in e.children() {
+        visitor.visit(child);
+    }
+}
+
+pub trait Folder {
+    /// Rewrites `e`. The default rewrites the children and keeps the node.
+    fn fold(&mut self, e: &Expr) -> Expr {
+        fold_children(self, e)
+    }
+}
+
+/// Rebuilds `e` with its children rewritten by `folder`.
+pub fn fold_children<F: Folder + ?Sized>(folder: &mut F, e: &Expr) -> Expr {
+    match e {
+        Expr::Un(op, x) => Expr::Un(*op, folder.fold(x).into()),
+        Expr::Bin(op, l, r) => {
+            let l = folder.fold(l);
+            let r = folder.fold(r);
+            Expr::Bin(*op, l.into(), r.into())
+        }
+        Expr::If(cond, th, el) => {
+            let cond = folder.fold(cond);
+            let th = folder.fold(th);
+            let el = folder.fold(el);
+            Expr::If(cond.into(), th.into(), el.into())
+        }
+        Expr::Lambda(v, body) => Expr::Lambda(*v, folder.fold(body).into()),
+        _ => e.clone(),
+    }
+}
+
+/// Collects the variables referenced outside of a lambda binding them.
+#[derive(Default)]
+struct FreeVars {
+    bound: Vec<usize>,
+    free: BTreeSet<usize>,
+}
+
+impl Visitor for FreeVars {
+    fn visit(&mut self, e: &Expr) {
+        match e {
+            Expr::Var(v) if !self.bound.contains(v) => {
+                self.free.insert(*v);
+            }
+            Expr::Lambda(v, _) => {
+                self.bound.push(*v);
+                walk(self, e);
+                self.bound.pop();
+            }
+            _ => walk(self, e),
+        }
+    }
+}
+
+impl Expr {
+    /// The children in the order they are encoded.
+    pub fn children(&self) -> impl DoubleEndedIterator<Item = &Expr> {
+        let children = match self {
+            Expr::Un(_, e) | Expr::Lambda(_, e) => [Some(e), None, None],
+            Expr::Bin(_, l, r) => [Some(l), Some(r), None],
+            Expr::If(cond, th, el) => [Some(cond), Some(th), Some(el)],
+            _ => [None, None, None],
+        };
+        children.into_iter().flatten().map(|e| e.as_ref())
+    }
+
+    pub fn free_vars(&self) -> BTreeSet<usize> {
+        let mut visitor = FreeVars::default();
+        visitor.visit(self);
+        visitor.free
+    }
+
+    /// Whether every variable is bound, which is required for evaluation.
+    pub fn is_closed(&self) -> bool {
+        self.free_vars().is_empty()
+    }
+
+    /// The length of `encoded()`. Negative integers cannot be encoded, and are
+    /// counted as their absolute values.
+    pub fn size_in_bytes(&self) -> usize {
+        let digits = |n: &BigInt| n.magnitude().to_radix_be(94).len();
+        match self {
+            Expr::Bool(_) => 1,
+            Expr::Int(n) => 1 + digits(n),
+            Expr::String(s) => 1 + s.chars().count(),
+            Expr::Var(v) => 1 + digits(&BigInt::from(*v)),
+            Expr::Lambda(v, body) => 2 + digits(&BigInt::from(*v)) + body.size_in_bytes(),
+            // Two-byte operators, each followed by a space.
+            Expr::Un(..) | Expr::Bin(..) => {
+                2 + self
+                    .children()
+                    .map(|c| 1 + c.size_in_bytes())
+                    .sum::<usize>()
+            }
+            Expr::If(..) => {
+                1 + self
+                    .children()
+                    .map(|c| 1 + c.size_in_bytes())
+                    .sum::<usize>()
+            }
+        }
+    }
+
+    /// The number of nodes.
+    pub fn num_nodes(&self) -> usize {
+        1 + self.children().map(|c| c.num_nodes()).sum::<usize>()
+    }
+
+    /// The number of nodes on the longest path from the root to a leaf. This
+    /// uses an explicit stack, so that it can tell whether a term is too deep
+    /// for the recursive analyses.
+    pub fn depth(&self) -> usize {
+        let mut max = 0;
+        let mut stack = vec![(self, 1)];
+        while let Some((e, depth)) = stack.pop() {
+            max = max.max(depth);
+            stack.extend(e.children().map(|c| (c, depth + 1)));
+        }
+        max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Expr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn free_vars() {
+        assert_eq!(parse("L! B+ v! v\"").free_vars(), BTreeSet::from([1]));
+        assert_eq!(parse("B$ L! v! v!").free_vars(), BTreeSet::from([0]));
+        assert!(parse("L! L\" B$ v! v\"").is_closed());
+        assert!(!parse("? v# I! I\"").is_closed());
+    }
+
+    #[test]
+    fn size_in_bytes() {
+        for s in [
+            "T",
+            "I/6",
+            "SB%,,/}Q/2,$_",
+            "U- I$",
+            "? B> I# I$ S4% S34",
+            "B$ B$ L# L$ v# B. SB%,,/ S}Q/2,$_ IK",
+            "L\"! v\"!",
+        ] {
+            assert_eq!(parse(s).size_in_bytes(), s.len(), "{s}");
+        }
+    }
+
+    #[test]
+    fn depth() {
+        assert_eq!(parse("I!").depth(), 1);
+        assert_eq!(parse("B+ I! U- I!").depth(), 3);
+        assert_eq!(parse("L! ? v! I! B$ v! I!").depth(), 4);
+    }
+
+    #[test]
+    fn num_nodes() {
+        assert_eq!(parse("I!").num_nodes(), 1);
+        assert_eq!(parse("L! ? v! I! B$ v! I!").num_nodes(), 7);
+    }
+
+    #[test]
+    fn folder() {
+        // Increments every integer literal.
+        struct Increment;
+        impl Folder for Increment {
+            fn fold(&mut self, e: &Expr) -> Expr {
+                match e {
+                    Expr::Int(n) => Expr::Int((n.as_ref() + 1u32).into()),
+                    _ => fold_children(self, e),
+                }
+            }
+        }
+        let e = Increment.fold(&parse("L! ? v! I! B+ I\" I#"));
+        assert_eq!(e.encoded().to_string(), "L! ? v! I\" B+ I# I$");
+    }
+
+    #[test]
+    fn visitor() {
+        // Counts the lambdas.
+        struct Lambdas(usize);
+        impl Visitor for Lambdas {
+            fn visit(&mut self, e: &Expr) {
+                if let Expr::Lambda(..) = e {
+                    self.0 += 1;
+                }
+                walk(self, e);
+            }
+        }
+        let mut lambdas = Lambdas(0);
+        lambdas.visit(&parse("B$ L! L\" v! L# B$ v# v#"));
+        assert_eq!(lambdas.0, 3);
+    }
+}
@@ -1,8 +1,12 @@
 pub mod base94;
 pub mod compiler;
 pub mod cps;
+pub mod cse;
+pub mod dsl;
 pub mod eval;
 pub mod expr;
 pub mod lambdaman;
 pub mod optimize;
 pub mod planar;
+pub mod renumber;
+pub mod typecheck;
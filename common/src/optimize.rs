@@ -1,8 +1,337 @@
-use crate::expr::Expr;
+//! A rewrite-based optimizer that makes programs shorter without changing
+//! their results.
+//!
+//! Each rewrite is only applied when it keeps the result of the program and
+//! does not make its encoding longer, so the optimized program can be
+//! submitted in place of the original one. The number of beta reductions may
+//! change, but not in a way that repeats work which was shared before.
 
-// TODO: impl
+use std::rc::Rc;
+
+use num_bigint::Sign;
+
+use crate::cse::share_common_subexprs;
+use crate::eval::{apply_bin, apply_un, substitute};
+use crate::expr::{BinOp, Expr};
+
+/// Rewrites are repeated until nothing changes, which happens quickly since
+/// every rewrite shrinks the term, but this bounds the work on huge programs.
+const MAX_PASSES: usize = 100;
+
+/// The rewrites recurse on the term, so deeper programs are returned as is
+/// instead of overflowing the stack.
+const MAX_DEPTH: usize = 2000;
+
+/// Optimizes `expr` by
+///
+/// - folding operators applied to literals,
+/// - fusing concatenations of string literals,
+/// - resolving `if` on literal conditions,
+/// - removing `let`s whose variable is unused,
+/// - inlining arguments that are used once or are small literals, and
+/// - binding repeated subterms to variables with `share_common_subexprs`.
+///
+/// Programs deeper than `MAX_DEPTH` are returned unchanged.
 pub fn optimize(expr: Expr) -> Expr {
-    // let mut expr = expr;
-    // opt_str(&mut expr);
-    expr
+    if expr.depth() > MAX_DEPTH {
+        return expr;
+    }
+    let mut expr = expr;
+    for _ in 0..MAX_PASSES {
+        let next = pass(&expr);
+        if next == expr {
+            break;
+        }
+        expr = next;
+    }
+    share_common_subexprs(&expr)
+}
+
+/// Optimizes the children of `e`, then `e` itself.
+fn pass(e: &Expr) -> Expr {
+    match e {
+        Expr::Un(op, x) => {
+            let x = pass(x);
+            let folded = x.is_nf().then(|| apply_un(*op, &x).ok()).flatten();
+            let e = Expr::Un(*op, x.into());
+            pick(e, folded)
+        }
+        Expr::Bin(op @ (BinOp::App | BinOp::AppL | BinOp::AppV), f, arg) => {
+            let f = pass(f);
+            let arg = pass(arg);
+            let reduced = beta(*op, &f, &arg);
+            let e = Expr::Bin(*op, f.into(), arg.into());
+            pick(e, reduced)
+        }
+        Expr::Bin(op, l, r) => {
+            let l = pass(l);
+            let r = pass(r);
+            let rewritten = if l.is_nf() && r.is_nf() {
+                apply_bin(*op, &l, &r).ok()
+            } else if *op == BinOp::Concat {
+                fuse_concat(&l, &r)
+            } else {
+                None
+            };
+            let e = Expr::Bin(*op, l.into(), r.into());
+            pick(e, rewritten)
+        }
+        Expr::If(cond, th, el) => match pass(cond) {
+            Expr::Bool(true) => pass(th),
+            Expr::Bool(false) => pass(el),
+            cond => Expr::If(cond.into(), pass(th).into(), pass(el).into()),
+        },
+        Expr::Lambda(v, body) => Expr::Lambda(*v, pass(body).into()),
+        _ => e.clone(),
+    }
+}
+
+/// Returns the rewritten term if it can be encoded and is not longer.
+fn pick(e: Expr, rewritten: Option<Expr>) -> Expr {
+    match rewritten {
+        Some(r) if size(&r).is_some_and(|n| size(&e).is_some_and(|m| n <= m)) => r,
+        _ => e,
+    }
+}
+
+/// Reduces `(λv. body) arg` when it preserves the result and the work shared
+/// by the application.
+fn beta(op: BinOp, f: &Expr, arg: &Expr) -> Option<Expr> {
+    let Expr::Lambda(v, body) = f else {
+        return None;
+    };
+    let value = matches!(arg, Expr::Lambda(..)) || arg.is_nf();
+    let (count, repeated) = uses(body, *v);
+    let safe = match op {
+        // Substitution is the meaning of call-by-name.
+        BinOp::App => true,
+        // The argument is evaluated at most once either way.
+        BinOp::AppL => value || (count <= 1 && !repeated),
+        // The argument is evaluated before the call, and may fail or diverge.
+        _ => value,
+    };
+    if !safe {
+        return None;
+    }
+    let reduced = substitute(body, *v, arg);
+    // Duplicating the argument usually grows the term, and `pick` would undo
+    // it, but it must strictly shrink so that the passes terminate.
+    let before = size(f)? + size(arg)? + 3;
+    (size(&reduced)? < before).then_some(reduced)
+}
+
+/// `"a" ++ ("b" ++ x)` to `"ab" ++ x` and `(x ++ "a") ++ "b"` to `x ++ "ab"`.
+/// Concatenation is associative, and fails if `x` is not a string either way.
+fn fuse_concat(l: &Expr, r: &Expr) -> Option<Expr> {
+    let concat = |l: Expr, r: &Rc<Expr>| Expr::Bin(BinOp::Concat, l.into(), r.clone());
+    match (l, r) {
+        (Expr::String(a), Expr::Bin(BinOp::Concat, b, x)) => {
+            let Expr::String(b) = b.as_ref() else {
+                return None;
+            };
+            Some(concat(Expr::String(format!("{a}{b}").into()), x))
+        }
+        (Expr::Bin(BinOp::Concat, x, a), Expr::String(b)) => {
+            let Expr::String(a) = a.as_ref() else {
+                return None;
+            };
+            Some(Expr::Bin(
+                BinOp::Concat,
+                x.clone(),
+                Expr::String(format!("{a}{b}").into()).into(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// The number of references to `v` in `e`, and whether any of them is under a
+/// lambda or in a call-by-name argument, where it may be evaluated more than
+/// once.
+fn uses(e: &Expr, v: usize) -> (usize, bool) {
+    match e {
+        Expr::Var(w) => ((*w == v) as usize, false),
+        Expr::Un(_, e) => uses(e, v),
+        Expr::Bin(BinOp::App, f, arg) => {
+            let (n, a) = uses(f, v);
+            let (m, _) = uses(arg, v);
+            (n + m, a || m > 0)
+        }
+        Expr::Bin(_, l, r) => {
+            let (n, a) = uses(l, v);
+            let (m, b) = uses(r, v);
+            (n + m, a || b)
+        }
+        Expr::If(cond, th, el) => {
+            let (n, a) = uses(cond, v);
+            let (m, b) = uses(th, v);
+            let (k, c) = uses(el, v);
+            (n + m + k, a || b || c)
+        }
+        Expr::Lambda(w, _) if *w == v => (0, false),
+        Expr::Lambda(_, body) => {
+            let (n, _) = uses(body, v);
+            (n, n > 0)
+        }
+        _ => (0, false),
+    }
+}
+
+/// The length of `e.encoded()`, or `None` if it contains a negative number,
+/// which cannot be encoded.
+fn size(e: &Expr) -> Option<usize> {
+    fn negative(e: &Expr) -> bool {
+        match e {
+            Expr::Int(n) => n.sign() == Sign::Minus,
+            _ => e.children().any(negative),
+        }
+    }
+    (!negative(e)).then(|| e.size_in_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use num_bigint::BigInt;
+
+    use super::*;
+    use crate::eval::{eval, eval_with_stats, EvalOptions, Strategy};
+
+    fn optimized(s: &str) -> String {
+        let e: Expr = s.parse().unwrap();
+        let opt = optimize(e.clone());
+        assert_eq!(size(&e), Some(e.encoded().to_string().len()));
+        assert!(size(&opt) <= size(&e), "{s} grew");
+        if e.is_nf() || eval(&e).is_ok_and(|v| v.is_nf()) {
+            assert_eq!(eval(&opt).unwrap(), eval(&e).unwrap(), "{s}");
+        }
+        opt.encoded().to_string()
+    }
+
+    #[test]
+    fn folding() {
+        assert_eq!(optimized("B+ I# I$"), "I&");
+        assert_eq!(optimized("B= B* I# I$ I'"), "T");
+        assert_eq!(optimized("U$ I4%34"), "S4%34");
+        assert_eq!(optimized("B. S4% S34"), "S4%34");
+        // Negative numbers cannot be encoded.
+        assert_eq!(optimized("B- I! I\""), "B- I! I\"");
+        // Errors are kept.
+        assert_eq!(optimized("B/ I\" I!"), "B/ I\" I!");
+        assert_eq!(optimized("B+ T I\""), "B+ T I\"");
+    }
+
+    #[test]
+    fn concat_fusion() {
+        assert_eq!(optimized("L! B. S# B. S$ v!"), "L! B. S#$ v!");
+        assert_eq!(optimized("L! B. B. v! S# S$"), "L! B. v! S#$");
+        assert_eq!(optimized("L! B. S# B. v! S$"), "L! B. S# B. v! S$");
+    }
+
+    #[test]
+    fn conditions() {
+        assert_eq!(optimized("? T I\" I#"), "I\"");
+        assert_eq!(optimized("? B< I\" I# S# S$"), "S#");
+        assert_eq!(optimized("L! ? v! I\" I#"), "L! ? v! I\" I#");
+    }
+
+    #[test]
+    fn dead_lets() {
+        assert_eq!(optimized("B$ L! I\" B+ T T"), "I\"");
+        assert_eq!(optimized("B~ L! I\" B+ T T"), "I\"");
+        // The call-by-value argument is evaluated, and fails.
+        assert_eq!(optimized("B! L! I\" B+ T T"), "B! L! I\" B+ T T");
+        assert_eq!(optimized("B! L! I\" I#"), "I\"");
+    }
+
+    #[test]
+    fn inlining() {
+        // Used once.
+        assert_eq!(optimized("L\" B$ L! B+ v! I\" v\""), "L\" B+ v\" I\"");
+        // Small literals.
+        assert_eq!(optimized("B$ L! B* v! v! I$"), "I*");
+        assert_eq!(
+            optimized("L\" B~ L! B* v! B+ v! v\" I$"),
+            "L\" B* I$ B+ I$ v\""
+        );
+        // Shared work is not repeated.
+        assert_eq!(
+            optimized("L# B~ L! L\" B+ v! v\" B* v# v#"),
+            "L# B~ L! L\" B+ v! v\" B* v# v#"
+        );
+        assert_eq!(
+            optimized("L# B~ L! B+ v! v! B* v# v#"),
+            "L# B~ L! B+ v! v! B* v# v#"
+        );
+        // Large arguments used twice are kept.
+        assert_eq!(
+            optimized("B$ L! B. v! v! SAAAAAAAAAAAAAAAAAAA"),
+            "B$ L! B. v! v! SAAAAAAAAAAAAAAAAAAA"
+        );
+    }
+
+    #[test]
+    fn beta_reductions() {
+        let beta_reductions = |e: &Expr| {
+            let options = EvalOptions {
+                strategy: Strategy::Substitution,
+                ..Default::default()
+            };
+            eval_with_stats(e, &options).unwrap().1.beta_reductions
+        };
+        // The sum of 1..30 with the fixed-point combinator.
+        let sum = "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I! B+ v# B$ v\" B- v# I\" I?";
+        for program in [
+            // (λx. (λy. y + y) (x + 1 + ... + 6)) ~sum: the shared argument
+            // must not be inlined into the call-by-name one used twice.
+            format!("B~ L# B$ L\" B+ v\" v\" B+ B+ B+ B+ B+ B+ v# I\" I# I$ I% I& I' {sum}"),
+            format!("B~ L# B$ L\" B+ B+ v\" v\" v\" B+ B+ B+ B+ B+ B+ v# I\" I# I$ I% I& I' {sum}"),
+            format!("B~ L# B+ v# I\" {sum}"),
+            sum.to_string(),
+        ] {
+            let e: Expr = program.parse().unwrap();
+            let opt = optimize(e.clone());
+            assert_eq!(eval(&opt).unwrap(), eval(&e).unwrap(), "{program}");
+            // Sharing the halves of the fixed-point combinator costs one
+            // reduction for the let.
+            assert!(
+                beta_reductions(&opt) <= beta_reductions(&e) + 1,
+                "{program} => {}",
+                opt.encoded()
+            );
+        }
+    }
+
+    #[test]
+    fn fixed_point() {
+        // The recursion is not unrolled, but the two halves of the fixed-point
+        // combinator are shared.
+        let factorial = "B$ B$ L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v# L\" L# ? B= v# I! I\" B* v# B$ v\" B- v# I\" I&";
+        assert_eq!(
+            optimized(factorial),
+            "B$ B$ L! B~ L& B$ v& v& L\" B$ v! B$ v\" v\" L$ L% ? B= v% I! I\" B* v% B$ v$ B- v% I\" I&"
+        );
+    }
+
+    #[test]
+    fn depth_limit() {
+        // 2 + (2 * (2 + ... (2 * 2))), which folds to a literal.
+        let chain = |depth: usize| {
+            let mut e = Expr::Int(BigInt::from(2).into());
+            for i in 1..depth {
+                let op = if i % 2 == 0 { BinOp::Add } else { BinOp::Mul };
+                e = Expr::Bin(op, Expr::Int(BigInt::from(2).into()).into(), e.into());
+            }
+            e
+        };
+        assert!(optimize(chain(100)).is_nf());
+        let e = chain(MAX_DEPTH + 1);
+        assert_eq!(e.depth(), MAX_DEPTH + 1);
+        let Expr::Bin(_, _, before) = &e else {
+            unreachable!()
+        };
+        let Expr::Bin(_, _, after) = optimize(e.clone()) else {
+            panic!("optimized too deep a term");
+        };
+        assert!(Rc::ptr_eq(before, &after));
+    }
 }
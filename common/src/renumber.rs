@@ -0,0 +1,273 @@
+//! Renumbering of bound variables to minimize the encoded size of a program.
+//!
+//! The score of a lambdaman solution is the byte length of the encoded
+//! program, where a variable costs as many bytes as its index has base94
+//! digits, once at its binder and once per reference. Only 94 indices (`!` to
+//! `~`) fit in a single byte, so they should go to the variables referenced
+//! most often, and be shared by every pair of lambdas that cannot capture each
+//! other's references.
+
+use std::collections::{BTreeSet, HashSet};
+
+use crate::expr::Expr;
+
+/// The result of [`renumber_vars`].
+pub struct Renumbered {
+    pub expr: Expr,
+    /// The encoded size of the original expression in bytes.
+    pub before: usize,
+    /// The encoded size of `expr` in bytes.
+    pub after: usize,
+}
+
+impl Renumbered {
+    pub fn saved(&self) -> usize {
+        self.before - self.after
+    }
+}
+
+impl std::fmt::Display for Renumbered {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "renumbered variables: {} -> {} bytes ({} saved)",
+            self.before,
+            self.after,
+            self.saved()
+        )
+    }
+}
+
+#[derive(Default)]
+struct Binder {
+    /// The number of bytes per digit of the index: the binder itself and its
+    /// references.
+    weight: usize,
+    /// Binders that must get a different index, i.e. ones on the path between
+    /// this binder and one of its references, or the other way around.
+    conflicts: HashSet<usize>,
+    /// Free variables referenced in the body, which the binder must not
+    /// capture.
+    free: BTreeSet<usize>,
+}
+
+/// Renumbers the bound variables of `e` to minimize its encoded size. Free
+/// variables are kept, and the expression is returned as is if renumbering
+/// does not make it shorter.
+pub fn renumber_vars(e: &Expr) -> Renumbered {
+    let mut binders = vec![];
+    collect(e, &mut vec![], &mut binders);
+
+    // Greedy coloring, heaviest binders first so that they get the
+    // single-byte indices when there are not enough of them.
+    let mut order: Vec<usize> = (0..binders.len()).collect();
+    order.sort_by_key(|&id| std::cmp::Reverse(binders[id].weight));
+    let mut assigned: Vec<Option<usize>> = vec![None; binders.len()];
+    for id in order {
+        let binder = &binders[id];
+        let used: BTreeSet<usize> = binder
+            .conflicts
+            .iter()
+            .filter_map(|&other| assigned[other])
+            .chain(binder.free.iter().copied())
+            .collect();
+        assigned[id] = (0..).find(|i| !used.contains(i));
+    }
+    let assigned: Vec<usize> = assigned.into_iter().map(Option::unwrap).collect();
+
+    let renumbered = rebuild(e, &assigned, &mut 0, &mut vec![]);
+    let before = e.size_in_bytes();
+    let after = renumbered.size_in_bytes();
+    if after < before {
+        Renumbered {
+            expr: renumbered,
+            before,
+            after,
+        }
+    } else {
+        Renumbered {
+            expr: e.clone(),
+            before,
+            after: before,
+        }
+    }
+}
+
+/// Numbers the binders of `e` in pre-order and records their weights and
+/// conflicts. `scope` holds the enclosing binders as (variable, binder id).
+fn collect(e: &Expr, scope: &mut Vec<(usize, usize)>, binders: &mut Vec<Binder>) {
+    match e {
+        Expr::Var(v) => match scope.iter().rposition(|(w, _)| w == v) {
+            Some(pos) => {
+                let id = scope[pos].1;
+                binders[id].weight += 1;
+                for &(_, inner) in &scope[pos + 1..] {
+                    binders[id].conflicts.insert(inner);
+                    binders[inner].conflicts.insert(id);
+                }
+            }
+            None => {
+                for &(_, inner) in scope.iter() {
+                    binders[inner].free.insert(*v);
+                }
+            }
+        },
+        Expr::Un(_, e) => collect(e, scope, binders),
+        Expr::Bin(_, l, r) => {
+            collect(l, scope, binders);
+            collect(r, scope, binders);
+        }
+        Expr::If(cond, th, el) => {
+            collect(cond, scope, binders);
+            collect(th, scope, binders);
+            collect(el, scope, binders);
+        }
+        Expr::Lambda(v, body) => {
+            let id = binders.len();
+            binders.push(Binder {
+                weight: 1,
+                ..Default::default()
+            });
+            scope.push((*v, id));
+            collect(body, scope, binders);
+            scope.pop();
+        }
+        _ => {}
+    }
+}
+
+/// Rebuilds `e` with the indices assigned to its binders, visiting them in the
+/// same order as `collect`.
+fn rebuild(
+    e: &Expr,
+    assigned: &[usize],
+    next: &mut usize,
+    scope: &mut Vec<(usize, usize)>,
+) -> Expr {
+    match e {
+        Expr::Var(v) => Expr::Var(
+            scope
+                .iter()
+                .rev()
+                .find(|(old, _)| old == v)
+                .map_or(*v, |(_, new)| *new),
+        ),
+        Expr::Un(op, e) => Expr::Un(*op, rebuild(e, assigned, next, scope).into()),
+        Expr::Bin(op, l, r) => {
+            let l = rebuild(l, assigned, next, scope);
+            let r = rebuild(r, assigned, next, scope);
+            Expr::Bin(*op, l.into(), r.into())
+        }
+        Expr::If(cond, th, el) => {
+            let cond = rebuild(cond, assigned, next, scope);
+            let th = rebuild(th, assigned, next, scope);
+            let el = rebuild(el, assigned, next, scope);
+            Expr::If(cond.into(), th.into(), el.into())
+        }
+        Expr::Lambda(v, body) => {
+            let new = assigned[*next];
+            *next += 1;
+            scope.push((*v, new));
+            let body = rebuild(body, assigned, next, scope);
+            scope.pop();
+            Expr::Lambda(new, body.into())
+        }
+        _ => e.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::eval::eval;
+    use crate::expr::BinOp;
+
+    fn app(f: Expr, x: Expr) -> Expr {
+        Expr::Bin(BinOp::App, Rc::new(f), Rc::new(x))
+    }
+
+    fn lambda(v: usize, body: Expr) -> Expr {
+        Expr::Lambda(v, Rc::new(body))
+    }
+
+    #[test]
+    fn disjoint_scopes() {
+        // (λ1000. v1000) (λ2000. λ3000. v2000 + v3000)
+        let e = app(
+            lambda(1000, Expr::Var(1000)),
+            lambda(
+                2000,
+                lambda(
+                    3000,
+                    Expr::Bin(
+                        BinOp::Add,
+                        Rc::new(Expr::Var(2000)),
+                        Rc::new(Expr::Var(3000)),
+                    ),
+                ),
+            ),
+        );
+        let r = renumber_vars(&e);
+        assert_eq!(r.expr.encoded().to_string(), "B$ L! v! L! L\" B+ v! v\"");
+        assert_eq!(r.before, 29);
+        assert_eq!(r.after, 23);
+        assert_eq!(r.saved(), 6);
+    }
+
+    #[test]
+    fn free_vars() {
+        // λ1000. v0 v1000 with a free v0 must not become λ0. v0 v0.
+        let e = lambda(1000, app(Expr::Var(0), Expr::Var(1000)));
+        let r = renumber_vars(&e);
+        assert_eq!(r.expr.encoded().to_string(), "L\" B$ v! v\"");
+        assert_eq!(r.saved(), 2);
+
+        // Only the free variables that the body refers to are avoided.
+        let e = app(Expr::Var(0), lambda(1000, Expr::Var(1000)));
+        let r = renumber_vars(&e);
+        assert_eq!(r.expr.encoded().to_string(), "B$ v! L! v!");
+    }
+
+    #[test]
+    fn frequent_vars_first() {
+        // 100 nested binders that are all live in the innermost body, where the
+        // outermost one has a two-byte index and is referenced far more often
+        // than the others.
+        let n = 100;
+        let mut body = Expr::Int(Rc::new(0.into()));
+        for v in 0..n {
+            body = Expr::Bin(BinOp::Add, Rc::new(body), Rc::new(Expr::Var(v)));
+        }
+        for _ in 0..50 {
+            body = Expr::Bin(BinOp::Add, Rc::new(body), Rc::new(Expr::Var(n - 1)));
+        }
+        let mut e = body;
+        for v in 0..n {
+            e = lambda(v, e);
+        }
+        let r = renumber_vars(&e);
+        let Expr::Lambda(outer, _) = r.expr else {
+            panic!("not a lambda: {}", r.expr);
+        };
+        assert!(outer < 94, "outermost binder got index {outer}");
+        assert_eq!(r.saved(), 50);
+
+        let mut applied = r.expr.clone();
+        let mut original = e.clone();
+        for i in 0..n {
+            applied = app(applied, Expr::Int(Rc::new(i.into())));
+            original = app(original, Expr::Int(Rc::new(i.into())));
+        }
+        assert_eq!(eval(&applied).unwrap(), eval(&original).unwrap());
+    }
+
+    #[test]
+    fn never_longer() {
+        let e = lambda(0, lambda(1, app(Expr::Var(0), Expr::Var(1))));
+        let r = renumber_vars(&e);
+        assert_eq!(r.expr, e);
+        assert_eq!(r.saved(), 0);
+    }
+}
@@ -0,0 +1,548 @@
+//! Hindley–Milner type inference for ICFP expressions.
+//!
+//! Most evaluation failures are type errors, such as concatenating an integer,
+//! which only show up after the evaluation reaches them. This finds them
+//! upfront, and reports the first ill-typed node.
+//!
+//! Applications of a lambda, i.e. `let`s, are generalized as in ML, so a
+//! helper bound once can be used at several types. Fixed-point combinators
+//! cannot be typed, as they apply a variable to itself, so they are recognized
+//! by their shape and given the type `(a -> a) -> a`. Other self-applications
+//! are rejected.
+
+use std::collections::BTreeSet;
+
+use crate::expr::{BinOp, Expr, UnOp};
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum Type {
+    Int,
+    Bool,
+    String,
+    Fun(Box<Type>, Box<Type>),
+    /// A type variable, which stands for any type.
+    Var(usize),
+}
+
+impl Type {
+    fn fun(arg: Type, ret: Type) -> Type {
+        Type::Fun(Box::new(arg), Box::new(ret))
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Bool => write!(f, "bool"),
+            Type::String => write!(f, "string"),
+            Type::Fun(arg, ret) if matches!(arg.as_ref(), Type::Fun(..)) => {
+                write!(f, "({arg}) -> {ret}")
+            }
+            Type::Fun(arg, ret) => write!(f, "{arg} -> {ret}"),
+            Type::Var(v) => write!(f, "t{v}"),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum TypeErrorKind {
+    Mismatch {
+        expected: Type,
+        found: Type,
+    },
+    /// The type would have to contain itself, as for `x` in `x x`.
+    Infinite {
+        expected: Type,
+        found: Type,
+    },
+    /// Functions were compared with `=`.
+    NotComparable(Type),
+    UnboundVariable(usize),
+}
+
+impl std::fmt::Display for TypeErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TypeErrorKind::Mismatch { expected, found } => {
+                write!(f, "expected {expected}, found {found}")
+            }
+            TypeErrorKind::Infinite { expected, found } => {
+                write!(f, "infinite type: expected {expected}, found {found}")
+            }
+            TypeErrorKind::NotComparable(ty) => write!(f, "cannot compare {ty} with `=`"),
+            TypeErrorKind::UnboundVariable(v) => write!(f, "unbound variable: v{v}"),
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct TypeError {
+    pub kind: TypeErrorKind,
+    /// The child indices from the root to the ill-typed node: the operand of a
+    /// unary operator, the left and right operands of a binary operator, the
+    /// condition and branches of an `if`, and the body of a lambda.
+    pub path: Vec<usize>,
+    /// The encoded node, truncated if it is long.
+    pub node: String,
+}
+
+/// Nodes are printed up to this many bytes.
+const MAX_NODE_LEN: usize = 60;
+
+impl std::fmt::Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "type error at /")?;
+        for (i, child) in self.path.iter().enumerate() {
+            if i > 0 {
+                write!(f, "/")?;
+            }
+            write!(f, "{child}")?;
+        }
+        write!(f, " ({}): {}", self.node, self.kind)
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+/// Infers the type of `e`.
+pub fn typecheck(e: &Expr) -> Result<Type, TypeError> {
+    let mut checker = Checker::default();
+    let ty = checker.infer(e)?;
+    for (path, node, ty) in std::mem::take(&mut checker.comparisons) {
+        let ty = rename(&checker.resolve(&ty), &mut vec![]);
+        if matches!(ty, Type::Fun(..)) {
+            return Err(TypeError {
+                kind: TypeErrorKind::NotComparable(ty),
+                path,
+                node: encode_node(&node),
+            });
+        }
+    }
+    Ok(rename(&checker.resolve(&ty), &mut vec![]))
+}
+
+/// Renames the type variables of `ty` to `t0`, `t1`, ... in the order they
+/// appear. `names` holds the variables already renamed.
+fn rename(ty: &Type, names: &mut Vec<usize>) -> Type {
+    match ty {
+        Type::Var(v) => match names.iter().position(|w| w == v) {
+            Some(i) => Type::Var(i),
+            None => {
+                names.push(*v);
+                Type::Var(names.len() - 1)
+            }
+        },
+        Type::Fun(arg, ret) => {
+            let arg = rename(arg, names);
+            Type::fun(arg, rename(ret, names))
+        }
+        ty => ty.clone(),
+    }
+}
+
+fn encode_node(node: &Expr) -> String {
+    let mut node = node.encoded().to_string();
+    if node.len() > MAX_NODE_LEN {
+        // Encoded expressions are ASCII.
+        node.truncate(MAX_NODE_LEN);
+        node.push_str(" ...");
+    }
+    node
+}
+
+/// A type that is polymorphic in `vars`.
+struct Scheme {
+    vars: Vec<usize>,
+    ty: Type,
+}
+
+#[derive(Default)]
+struct Checker {
+    /// The types assigned to type variables.
+    subst: Vec<Option<Type>>,
+    env: Vec<(usize, Scheme)>,
+    path: Vec<usize>,
+    /// The operands of `=`, which must not be functions once their types are
+    /// known.
+    comparisons: Vec<(Vec<usize>, Expr, Type)>,
+}
+
+/// The reason two types do not unify.
+enum Conflict {
+    Mismatch,
+    Infinite,
+}
+
+impl Checker {
+    fn fresh(&mut self) -> Type {
+        self.subst.push(None);
+        Type::Var(self.subst.len() - 1)
+    }
+
+    /// Follows the substitution at the top of `ty`.
+    fn prune(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let Type::Var(v) = ty {
+            match &self.subst[v] {
+                Some(t) => ty = t.clone(),
+                None => break,
+            }
+        }
+        ty
+    }
+
+    /// Applies the substitution everywhere in `ty`.
+    fn resolve(&self, ty: &Type) -> Type {
+        match self.prune(ty) {
+            Type::Fun(arg, ret) => Type::fun(self.resolve(&arg), self.resolve(&ret)),
+            ty => ty,
+        }
+    }
+
+    fn occurs(&self, v: usize, ty: &Type) -> bool {
+        match self.prune(ty) {
+            Type::Var(w) => v == w,
+            Type::Fun(arg, ret) => self.occurs(v, &arg) || self.occurs(v, &ret),
+            _ => false,
+        }
+    }
+
+    fn unify_inner(&mut self, a: &Type, b: &Type) -> Result<(), Conflict> {
+        match (self.prune(a), self.prune(b)) {
+            (Type::Var(v), Type::Var(w)) if v == w => Ok(()),
+            (Type::Var(v), t) | (t, Type::Var(v)) => {
+                if self.occurs(v, &t) {
+                    return Err(Conflict::Infinite);
+                }
+                self.subst[v] = Some(t);
+                Ok(())
+            }
+            (Type::Fun(a1, r1), Type::Fun(a2, r2)) => {
+                self.unify_inner(&a1, &a2)?;
+                self.unify_inner(&r1, &r2)
+            }
+            (a, b) if a == b => Ok(()),
+            _ => Err(Conflict::Mismatch),
+        }
+    }
+
+    /// Unifies the type `found` for `node` with `expected`.
+    fn unify(&mut self, expected: &Type, found: &Type, node: &Expr) -> Result<(), TypeError> {
+        self.unify_inner(expected, found).map_err(|conflict| {
+            let mut names = vec![];
+            let expected = rename(&self.resolve(expected), &mut names);
+            let found = rename(&self.resolve(found), &mut names);
+            let kind = match conflict {
+                Conflict::Mismatch => TypeErrorKind::Mismatch { expected, found },
+                Conflict::Infinite => TypeErrorKind::Infinite { expected, found },
+            };
+            self.error(kind, node)
+        })
+    }
+
+    fn error(&self, kind: TypeErrorKind, node: &Expr) -> TypeError {
+        TypeError {
+            kind,
+            path: self.path.clone(),
+            node: encode_node(node),
+        }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let fresh: Vec<(usize, Type)> = scheme.vars.iter().map(|&v| (v, self.fresh())).collect();
+        self.substitute(&scheme.ty, &fresh)
+    }
+
+    fn substitute(&self, ty: &Type, fresh: &[(usize, Type)]) -> Type {
+        match self.prune(ty) {
+            Type::Var(v) => fresh
+                .iter()
+                .find(|(w, _)| *w == v)
+                .map_or(Type::Var(v), |(_, t)| t.clone()),
+            Type::Fun(arg, ret) => {
+                Type::fun(self.substitute(&arg, fresh), self.substitute(&ret, fresh))
+            }
+            ty => ty,
+        }
+    }
+
+    fn free_vars(&self, ty: &Type, vars: &mut BTreeSet<usize>) {
+        match self.prune(ty) {
+            Type::Var(v) => {
+                vars.insert(v);
+            }
+            Type::Fun(arg, ret) => {
+                self.free_vars(&arg, vars);
+                self.free_vars(&ret, vars);
+            }
+            _ => {}
+        }
+    }
+
+    /// Quantifies the variables of `ty` that are not bound in the environment.
+    fn generalize(&self, ty: &Type) -> Scheme {
+        let mut bound = BTreeSet::new();
+        for (_, scheme) in &self.env {
+            let mut vars = BTreeSet::new();
+            self.free_vars(&scheme.ty, &mut vars);
+            bound.extend(vars.into_iter().filter(|v| !scheme.vars.contains(v)));
+        }
+        let mut vars = BTreeSet::new();
+        self.free_vars(ty, &mut vars);
+        Scheme {
+            vars: vars.difference(&bound).copied().collect(),
+            ty: self.resolve(ty),
+        }
+    }
+
+    /// Infers the type of the `i`-th child of the current node.
+    fn child(&mut self, i: usize, e: &Expr) -> Result<Type, TypeError> {
+        self.path.push(i);
+        let ty = self.infer(e);
+        self.path.pop();
+        ty
+    }
+
+    /// Checks that the `i`-th child of the current node has type `expected`.
+    fn expect(&mut self, i: usize, e: &Expr, expected: Type) -> Result<(), TypeError> {
+        self.path.push(i);
+        let ret = self.infer(e).and_then(|ty| self.unify(&expected, &ty, e));
+        self.path.pop();
+        ret
+    }
+
+    fn infer(&mut self, e: &Expr) -> Result<Type, TypeError> {
+        if is_fix(e) {
+            let a = self.fresh();
+            return Ok(Type::fun(Type::fun(a.clone(), a.clone()), a));
+        }
+        Ok(match e {
+            Expr::Bool(_) => Type::Bool,
+            Expr::Int(_) => Type::Int,
+            Expr::String(_) => Type::String,
+            Expr::Var(v) => {
+                let Some(pos) = self.env.iter().rposition(|(w, _)| w == v) else {
+                    return Err(self.error(TypeErrorKind::UnboundVariable(*v), e));
+                };
+                let scheme = Scheme {
+                    vars: self.env[pos].1.vars.clone(),
+                    ty: self.env[pos].1.ty.clone(),
+                };
+                self.instantiate(&scheme)
+            }
+            Expr::Un(op, x) => {
+                let (arg, ret) = match op {
+                    UnOp::Neg => (Type::Int, Type::Int),
+                    UnOp::Not => (Type::Bool, Type::Bool),
+                    UnOp::StrToInt => (Type::String, Type::Int),
+                    UnOp::IntToStr => (Type::Int, Type::String),
+                };
+                self.expect(0, x, arg)?;
+                ret
+            }
+            Expr::Bin(BinOp::App | BinOp::AppL | BinOp::AppV, f, arg) => {
+                if let (Expr::Lambda(v, body), false) = (f.as_ref(), is_fix(f)) {
+                    // A `let`, whose variable may be used at different types.
+                    let ty = self.child(1, arg)?;
+                    let scheme = self.generalize(&ty);
+                    self.path.extend([0, 0]);
+                    self.env.push((*v, scheme));
+                    let ret = self.infer(body);
+                    self.env.pop();
+                    self.path.truncate(self.path.len() - 2);
+                    return ret;
+                }
+                let (param, ret) = (self.fresh(), self.fresh());
+                self.expect(0, f, Type::fun(param.clone(), ret.clone()))?;
+                self.expect(1, arg, param)?;
+                ret
+            }
+            Expr::Bin(BinOp::Eq, l, r) => {
+                let ty = self.child(0, l)?;
+                self.expect(1, r, ty.clone())?;
+                self.comparisons.push((self.path.clone(), e.clone(), ty));
+                Type::Bool
+            }
+            Expr::Bin(op, l, r) => {
+                let (lhs, rhs, ret) = match op {
+                    BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div | BinOp::Mod => {
+                        (Type::Int, Type::Int, Type::Int)
+                    }
+                    BinOp::Lt | BinOp::Gt => (Type::Int, Type::Int, Type::Bool),
+                    BinOp::Or | BinOp::And => (Type::Bool, Type::Bool, Type::Bool),
+                    BinOp::Concat => (Type::String, Type::String, Type::String),
+                    BinOp::Take | BinOp::Drop => (Type::Int, Type::String, Type::String),
+                    BinOp::Eq | BinOp::App | BinOp::AppL | BinOp::AppV => unreachable!(),
+                };
+                self.expect(0, l, lhs)?;
+                self.expect(1, r, rhs)?;
+                ret
+            }
+            Expr::If(cond, th, el) => {
+                self.expect(0, cond, Type::Bool)?;
+                let ty = self.child(1, th)?;
+                self.expect(2, el, ty.clone())?;
+                ty
+            }
+            Expr::Lambda(v, body) => {
+                let param = self.fresh();
+                self.env.push((
+                    *v,
+                    Scheme {
+                        vars: vec![],
+                        ty: param.clone(),
+                    },
+                ));
+                let ret = self.child(0, body);
+                self.env.pop();
+                Type::fun(param, ret?)
+            }
+        })
+    }
+}
+
+/// Whether `e` is a fixed-point combinator: `λf. M M`, or `λf. (λm. m m) M`
+/// as shared by `optimize`, where `M` is `λx. f (x x)` or `λx. f (λy. x x y)`.
+pub fn is_fix(e: &Expr) -> bool {
+    let Expr::Lambda(f, body) = e else {
+        return false;
+    };
+    let Some((l, r)) = app(body) else {
+        return false;
+    };
+    let half = |m: &Expr| {
+        let Expr::Lambda(x, body) = m else {
+            return false;
+        };
+        let Some((g, arg)) = app(body) else {
+            return false;
+        };
+        let self_app = |e: &Expr| app(e).is_some_and(|(a, b)| is_var(a, *x) && is_var(b, *x));
+        let delayed = |e: &Expr| match e {
+            Expr::Lambda(y, body) if y != x => {
+                app(body).is_some_and(|(xx, b)| self_app(xx) && is_var(b, *y))
+            }
+            _ => false,
+        };
+        x != f && is_var(g, *f) && (self_app(arg) || delayed(arg))
+    };
+    // The halves may use different names for `x`.
+    if half(l) && half(r) {
+        return true;
+    }
+    // `(λm. m m) M`
+    match l {
+        Expr::Lambda(m, body) => {
+            app(body).is_some_and(|(a, b)| is_var(a, *m) && is_var(b, *m)) && half(r)
+        }
+        _ => false,
+    }
+}
+
+fn app(e: &Expr) -> Option<(&Expr, &Expr)> {
+    match e {
+        Expr::Bin(BinOp::App | BinOp::AppL | BinOp::AppV, f, x) => Some((f, x)),
+        _ => None,
+    }
+}
+
+fn is_var(e: &Expr, v: usize) -> bool {
+    matches!(e, Expr::Var(w) if *w == v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(s: &str) -> String {
+        match typecheck(&s.parse().unwrap()) {
+            Ok(ty) => ty.to_string(),
+            Err(err) => panic!("{s}: {err}"),
+        }
+    }
+
+    fn error(s: &str) -> String {
+        typecheck(&s.parse().unwrap()).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn literals_and_operators() {
+        assert_eq!(typed("I$"), "int");
+        assert_eq!(typed("B. S4% U$ I4%34"), "string");
+        assert_eq!(typed("? B< I$ I# T B= S! S\""), "bool");
+        assert_eq!(typed("BT I$ S4%34"), "string");
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(typed("L! B+ v! I\""), "int -> int");
+        assert_eq!(typed("L! L\" B$ v! v\""), "(t0 -> t1) -> t0 -> t1");
+        assert_eq!(typed("B$ B$ L# L$ v# B. SB%,,/ S}Q/2,$_ IK"), "string");
+    }
+
+    #[test]
+    fn let_polymorphism() {
+        // The identity is used on an integer and on a string.
+        assert_eq!(
+            typed("B$ L! B$ B$ L\" L# v\" B$ v! I\" B$ v! S! L! v!"),
+            "int"
+        );
+        // Lambda-bound variables are not generalized.
+        assert_eq!(
+            error("L! B$ B$ L\" L# v\" B$ v! I\" B$ v! S!"),
+            "type error at /0/1/1 (S!): expected int, found string"
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            error("B. S! I!"),
+            "type error at /1 (I!): expected string, found int"
+        );
+        assert_eq!(
+            error("B+ I\" ? I! I\" I#"),
+            "type error at /1/0 (I!): expected bool, found int"
+        );
+        assert_eq!(
+            error("B$ I! I\""),
+            "type error at /0 (I!): expected t0 -> t1, found int"
+        );
+        assert_eq!(
+            error("B= L! v! L! v!"),
+            "type error at / (B= L! v! L! v!): cannot compare t0 -> t0 with `=`"
+        );
+        assert_eq!(
+            error("B+ v! I!"),
+            "type error at /0 (v!): unbound variable: v0"
+        );
+        assert_eq!(
+            error("L! B$ v! v!"),
+            "type error at /0/1 (v!): infinite type: expected t0, found t0 -> t1"
+        );
+    }
+
+    #[test]
+    fn fixed_points() {
+        let y = "L\" B$ L# B$ v\" B$ v# v# L# B$ v\" B$ v# v#";
+        let factorial = format!("B$ B$ {y} L\" L# ? B= v# I! I\" B* v# B$ v\" B- v# I\" I&");
+        assert_eq!(typed(&factorial), "int");
+        let z = "L! B! L\" B$ v! L# B$ B$ v\" v\" v# L\" B$ v! L# B$ B$ v\" v\" v#";
+        assert_eq!(typed(z), "(t0 -> t0) -> t0");
+        // With different names in the two halves.
+        let y2 = "L\" B$ L# B$ v\" B$ v# v# L$ B$ v\" B$ v$ v$";
+        assert!(is_fix(&y2.parse().unwrap()));
+        assert_eq!(typed(y2), "(t0 -> t0) -> t0");
+        // As shared by `optimize`.
+        let shared = "B$ B$ L! B~ L& B$ v& v& L\" B$ v! B$ v\" v\" L$ L% ? B= v% I! I\" B* v% B$ v$ B- v% I\" I&";
+        assert_eq!(typed(shared), "int");
+        // The recursive function must be typed consistently.
+        let bad = format!("B$ B$ {y} L\" L# ? B= v# I! S! B* v# B$ v\" B- v# I\" I&");
+        assert!(
+            error(&bad).contains("expected string, found int"),
+            "{}",
+            error(&bad)
+        );
+    }
+}
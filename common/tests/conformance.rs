@@ -0,0 +1,199 @@
+//! Runs the downloaded problems and the saved solutions through the
+//! evaluator.
+//!
+//! Problems with a decoded `.txt` next to them must evaluate to its content.
+//! The others are mostly efficiency problems, which cannot be evaluated
+//! directly. Their types, sizes and optimized programs are compared with the
+//! snapshots in `tests/snapshots` instead, along with how far a bounded
+//! evaluation gets. Set `UPDATE_SNAPSHOTS=1` to record new snapshots, or all of
+//! them again after an intended change.
+
+use std::path::{Path, PathBuf};
+
+use common::{
+    eval::{eval_with, eval_with_stats, EvalError, EvalOptions},
+    expr::{parse_surface, Expr},
+    lambdaman::map::LMap,
+    optimize::optimize,
+    typecheck::typecheck,
+};
+
+fn repo_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..")
+}
+
+/// Lists the files in `dir` with the extension, sorted by their numeric
+/// prefix.
+fn list_files(dir: &Path, extension: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == extension))
+        .collect();
+    files.sort_by_key(|path| problem_id(path));
+    files
+}
+
+/// The problem number at the start of a file name, e.g. 4 for "4-348.txt".
+fn problem_id(path: &Path) -> usize {
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    let digits: String = stem.chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().unwrap()
+}
+
+/// The problems with a decoded `.txt` are evaluated within the limit of the
+/// contest. The others are mostly efficiency problems, which are not meant to
+/// be evaluated directly, so they only get a small budget.
+fn options(max_beta_reductions: usize) -> EvalOptions {
+    EvalOptions {
+        max_beta_reductions: Some(max_beta_reductions),
+        ..Default::default()
+    }
+}
+
+fn check_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(name);
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        eprintln!("recorded {}", path.display());
+        return;
+    }
+    let Ok(expected) = std::fs::read_to_string(&path) else {
+        panic!("snapshot {name} is missing, run with UPDATE_SNAPSHOTS=1 to record it:\n{actual}");
+    };
+    assert_eq!(actual, expected, "snapshot {name} differs");
+}
+
+/// Runs `f` with a larger stack than the test threads get, for the recursive
+/// passes over the deepest problems.
+fn with_large_stack(f: impl FnOnce() + Send + 'static) {
+    std::thread::Builder::new()
+        .stack_size(64 << 20)
+        .spawn(f)
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn problems() {
+    with_large_stack(check_problems);
+}
+
+fn check_problems() {
+    let problems = repo_root().join("problems");
+    let mut categories: Vec<PathBuf> = std::fs::read_dir(&problems)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_dir())
+        .collect();
+    categories.sort();
+
+    for category in categories {
+        let name = category.file_name().unwrap().to_str().unwrap().to_string();
+        for raw in list_files(&category, "raw") {
+            // The first line is the encoded response, the rest is for humans.
+            let content = std::fs::read_to_string(&raw).unwrap();
+            let expr: Expr = content.lines().next().unwrap().parse().unwrap();
+            let id = problem_id(&raw);
+
+            // Decoded programs can be edited in the readable syntax.
+            let surface = expr.surface().to_string();
+            assert_eq!(parse_surface(&surface).unwrap(), expr, "{}", raw.display());
+
+            let txt = raw.with_extension("txt");
+            if txt.exists() {
+                // Some of the decoded files have an extra newline at the end.
+                let expected = std::fs::read_to_string(&txt).unwrap();
+                let Expr::String(actual) = eval_with(&expr, &options(10_000_000)).unwrap() else {
+                    panic!("{} does not evaluate to a string", raw.display());
+                };
+                assert_eq!(
+                    actual.trim_end_matches('\n'),
+                    expected.trim_end_matches('\n'),
+                    "{}",
+                    raw.display()
+                );
+
+                // The optimizer keeps the result and never makes it longer.
+                let optimized = optimize(expr.clone());
+                assert!(
+                    optimized.encoded().to_string().len() <= content.lines().next().unwrap().len()
+                );
+                let Expr::String(optimized) = eval_with(&optimized, &options(10_000_000)).unwrap()
+                else {
+                    panic!(
+                        "{} does not evaluate to a string when optimized",
+                        raw.display()
+                    );
+                };
+                assert_eq!(optimized, actual, "{}", raw.display());
+                continue;
+            }
+
+            let ty = match typecheck(&expr) {
+                Ok(ty) => ty.to_string(),
+                Err(e) => format!("error: {e}"),
+            };
+            let mut actual = format!(
+                "type: {ty}\nsize: {}\ndepth: {}\n\n{}\n\n",
+                expr.size_in_bytes(),
+                expr.depth(),
+                optimize(expr.clone()).surface(),
+            );
+            actual += &match eval_with_stats(&expr, &options(100_000)) {
+                Ok((e, stats)) => format!("{e}\n\n{stats}\n"),
+                // The elapsed time is left out to keep the snapshot stable.
+                Err(EvalError::LimitExceeded { limit, .. }) => {
+                    format!("error: exceeded the limit of {limit}\n")
+                }
+                Err(e) => format!("error: {e}\n"),
+            };
+            check_snapshot(&format!("{name}-{id}.txt"), &actual);
+        }
+    }
+}
+
+#[test]
+fn lambdaman_solutions() {
+    let root = repo_root();
+    for path in list_files(&root.join("solutions/lambdaman"), "txt") {
+        let id = problem_id(&path);
+        let content = std::fs::read_to_string(&path).unwrap();
+        let content = content.trim();
+
+        // Solutions are either the moves or an ICFP program computing
+        // "solve lambdamanN <moves>".
+        let moves = if content.chars().all(|c| "UDLR".contains(c)) {
+            content.to_string()
+        } else {
+            let expr: Expr = content.parse().unwrap();
+            let Expr::String(s) = eval_with(&expr, &EvalOptions::default()).unwrap() else {
+                panic!("{} does not evaluate to a string", path.display());
+            };
+            let optimized = optimize(expr);
+            assert!(optimized.encoded().to_string().len() <= content.len());
+            assert_eq!(
+                eval_with(&optimized, &EvalOptions::default()).unwrap(),
+                Expr::String(s.clone()),
+                "{} changes when optimized",
+                path.display()
+            );
+            let prefix = format!("solve lambdaman{id} ");
+            s.strip_prefix(&prefix).unwrap_or(&s).to_string()
+        };
+
+        let map = std::fs::read_to_string(root.join(format!("problems/lambdaman/{id}.txt")));
+        let mut map: LMap = map.unwrap().parse().unwrap();
+        map.do_move(&moves).unwrap();
+        assert_eq!(
+            map.remaining_pills(),
+            0,
+            "{} leaves pills behind",
+            path.display()
+        );
+    }
+}
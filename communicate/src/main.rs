@@ -1,10 +1,12 @@
 use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::time::Duration;
 
 use anyhow::{bail, ensure, Result};
 use clap::Parser;
-use common::eval::eval;
-use common::expr::{Expr, Token};
+use common::eval::{eval_with, eval_with_stats, EvalOptions};
+use common::expr::{parse, Expr, Token};
+use common::typecheck::typecheck;
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
 
@@ -16,6 +18,47 @@ fn get_api_url_from_env() -> String {
     std::env::var("API_URL").unwrap_or("https://icfp-api.badalloc.com/communicate".to_string())
 }
 
+fn eval_options() -> EvalOptions {
+    EvalOptions {
+        max_beta_reductions: Some(10_000_000),
+        timeout: Some(Duration::from_secs(60)),
+        ..Default::default()
+    }
+}
+
+/// Evaluates a raw request locally and prints its statistics, so that we can
+/// tell whether the server can evaluate it within its limits. Returns false if
+/// the request should not be sent, i.e. when it does not parse or, unless
+/// `no_typecheck` is set, when it is ill-typed. A failed local evaluation,
+/// e.g. one that hits the time limit, is only a warning, as the server gets
+/// the final say.
+fn check_request(request: &str, no_typecheck: bool) -> bool {
+    let expr = match parse(request) {
+        Ok((expr, _)) => expr,
+        Err(err) => {
+            eprintln!("{}", err.diagnostic(request));
+            return false;
+        }
+    };
+    if !no_typecheck {
+        if let Err(err) = typecheck(&expr) {
+            eprintln!("*** The request is ill-typed: {err} ***");
+            eprintln!("Pass --no-typecheck to send it anyway.");
+            return false;
+        }
+    }
+    match eval_with_stats(&expr, &eval_options()) {
+        Ok((_, stats)) => {
+            eprintln!("{}", stats);
+            true
+        }
+        Err(err) => {
+            eprintln!("*** Failed to evaluate the request locally: {err} ***");
+            true
+        }
+    }
+}
+
 fn find_history_file() -> Result<PathBuf> {
     let current_dir = Path::new(".").canonicalize().unwrap();
     for dir in current_dir.ancestors() {
@@ -37,6 +80,11 @@ struct Args {
     #[arg(long)]
     raw_input: bool,
 
+    /// Sends raw requests even if they are ill-typed, e.g. ones that rely on
+    /// self-application other than fixed-point combinators.
+    #[arg(long)]
+    no_typecheck: bool,
+
     request: Option<String>,
 }
 
@@ -52,6 +100,9 @@ fn main() -> Result<ExitCode> {
 
     if let Some(request) = args.request {
         let request = if args.raw_input {
+            if !check_request(&request, args.no_typecheck) {
+                return Ok(ExitCode::FAILURE);
+            }
             request
         } else {
             Token::String(request).encoded().to_string()
@@ -73,12 +124,16 @@ fn main() -> Result<ExitCode> {
 
         if let Ok(expr) = text.parse::<Expr>() {
             eprintln!("{}", expr);
-            let expr = eval(&expr)?;
-            if let Expr::String(s) = expr {
-                println!("{}", s);
-            } else {
-                eprintln!("*** Failed to evaluate the response! ***");
-                return Ok(ExitCode::FAILURE);
+            match eval_with(&expr, &eval_options()) {
+                Ok(Expr::String(s)) => println!("{}", s),
+                Ok(_) => {
+                    eprintln!("*** Failed to evaluate the response! ***");
+                    return Ok(ExitCode::FAILURE);
+                }
+                Err(err) => {
+                    eprintln!("*** Failed to evaluate the response: {err} ***");
+                    return Ok(ExitCode::FAILURE);
+                }
             }
         }
 
@@ -122,6 +177,9 @@ fn main() -> Result<ExitCode> {
         }
 
         let request = if args.raw_input {
+            if !check_request(&line, args.no_typecheck) {
+                continue;
+            }
             line
         } else {
             Token::String(line).encoded().to_string()
@@ -143,11 +201,10 @@ fn main() -> Result<ExitCode> {
 
         if let Ok(expr) = text.parse::<Expr>() {
             eprintln!("{}", expr);
-            let expr = eval(&expr)?;
-            if let Expr::String(s) = expr {
-                println!("{}", s);
-            } else {
-                eprintln!("*** Failed to evaluate the response! ***");
+            match eval_with(&expr, &eval_options()) {
+                Ok(Expr::String(s)) => println!("{}", s),
+                Ok(_) => eprintln!("*** Failed to evaluate the response! ***"),
+                Err(err) => eprintln!("*** Failed to evaluate the response: {err} ***"),
             }
         }
     }
@@ -1,126 +1,8 @@
-use std::{
-    rc::Rc,
-    sync::atomic::{AtomicUsize, Ordering},
-};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use common::{
-    eval::eval,
-    expr::{BinOp, Expr},
-    lambdaman::map::LMap,
-};
-use num_bigint::BigInt;
+use common::{eval::eval, expr::Expr, icfp, lambdaman::map::LMap};
 use rayon::prelude::*;
 
-trait ToExpr {
-    fn to_expr(&self) -> Expr;
-}
-
-impl ToExpr for BigInt {
-    fn to_expr(&self) -> Expr {
-        Expr::Int(self.clone().into())
-    }
-}
-
-impl ToExpr for String {
-    fn to_expr(&self) -> Expr {
-        Expr::String(self.clone().into())
-    }
-}
-
-impl ToExpr for &str {
-    fn to_expr(&self) -> Expr {
-        Expr::String(self.to_string().into())
-    }
-}
-
-impl ToExpr for u64 {
-    fn to_expr(&self) -> Expr {
-        Expr::Int(BigInt::from(*self).into())
-    }
-}
-
-impl ToExpr for Expr {
-    fn to_expr(&self) -> Expr {
-        self.clone()
-    }
-}
-
-macro_rules! icfp {
-    (fix $($args:tt)+) => {
-        icfp! {
-            (fn f -> ((fn x -> (f (x x))) (fn x -> (f (x x))))) $($args)*
-        }
-    };
-    (fn $($args:ident)+ -> $body:tt) => {{
-        let mut e = icfp!{ $body };
-        for arg in [$(stringify!($args)), *].iter().rev() {
-            e = Expr::Lambda(varid(arg), Rc::new(e));
-        }
-        e
-    }};
-    (let $var:ident = $val:tt in $($body:tt)+) => {
-        Expr::Bin(BinOp::AppL,
-            Rc::new(Expr::Lambda(varid(stringify!($var)), Rc::new(icfp!{ $($body)* }))),
-            Rc::new(icfp!{ $val }))
-    };
-    (if $cond:tt { $($th:tt)+ } else { $($el:tt)+ }) => {
-        Expr::If(Rc::new(icfp!{ $cond }), Rc::new(icfp!{ $($th)* }), Rc::new(icfp!{ $($el)* }))
-    };
-    (concat $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Concat, Rc::new(icfp!{ $a1 }), Rc::new(icfp!{ $a2 }))
-    };
-    (take $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Take, Rc::new(icfp!{ $a1 }), Rc::new(icfp!{ $a2 }))
-    };
-    (drop $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Drop, Rc::new(icfp!{ $a1 }), Rc::new(icfp!{ $a2 }))
-    };
-    (== $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Eq, Rc::new(icfp!{ $a1 }), Rc::new(icfp!{ $a2 }))
-    };
-    (< $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Lt, Rc::new(icfp!{ $a1 }), Rc::new(icfp!{ $a2 }))
-    };
-    (+ $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Add, Rc::new(icfp!{ $a1 }), Rc::new(icfp!{ $a2 }))
-    };
-    (- $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Sub, Rc::new(icfp!{ $a1 }), Rc::new(icfp!{ $a2 }))
-    };
-    (* $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Mul, Rc::new(icfp!{ $a1 }), Rc::new(icfp!{ $a2 }))
-    };
-    (/ $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Div, Rc::new(icfp!{ $a1 }), Rc::new(icfp!{ $a2 }))
-    };
-    (% $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Mod, Rc::new(icfp!{ $a1 }), Rc::new(icfp!{ $a2 }))
-    };
-    (# $var:ident) => {
-        ToExpr::to_expr(&$var)
-    };
-    ($f:tt $($args:tt)+) => {{
-        let mut e = icfp!{ $f };
-        $(
-            e = Expr::Bin(BinOp::AppL, Rc::new(e), Rc::new(icfp!{ $args }));
-        )+
-        e
-    }};
-    ($var:ident) => {
-        Expr::Var(varid(stringify!($var)))
-    };
-    ($val:literal) => {
-        ToExpr::to_expr(&$val)
-    };
-    (($($tt:tt)+)) => {
-        icfp! { $($tt)+ }
-    };
-}
-
-fn varid(s: &str) -> usize {
-    s.chars().next().unwrap() as usize - 'A' as usize
-}
-
 fn problem6() -> Expr {
     let header = "solve lambdaman6 ";
 
@@ -149,6 +31,7 @@ fn problem6() -> Expr {
     //   SLLLLLLLLLLLLL
     //
     icfp! {
+        lazy;
         let x = "RRRRRRRRRRRRR" in
         let x = (concat (concat x x) (concat x x)) in
         (concat (#header) (concat (concat x x) (concat x x)))
@@ -157,6 +40,7 @@ fn problem6() -> Expr {
     // 87 bytes
     // B$ L0 B$ L8 B. S3/,6%},!-"$!-!.[} B$ v0 B$ v0 v8 SLLLLLLLLLLLLL L8 B. B. v8 v8 B. v8 v8
     icfp! {
+        lazy;
         let p = (fn x -> (concat (concat x x) (concat x x))) in
         let x = "RRRRRRRRRRRRR" in
         (concat (#header) (p (p x)))
@@ -184,6 +68,7 @@ fn problem6() -> Expr {
     //         v!
     //         v!
     icfp! {
+        lazy;
         let f = (fn x -> (concat (concat x x) (concat x x))) in
         (concat
             (#header)
@@ -194,6 +79,7 @@ fn problem6() -> Expr {
     // 74 bytes
     // B$ L& B. S3/,6%},!-"$!-!.[} B$ v& B$ v& B$ v& B$ v& SLLL L8 B. v8 B. v8 v8
     icfp! {
+        lazy;
         let f = (fn x -> (concat x (concat x x))) in
         (concat
             (#header)
@@ -204,6 +90,7 @@ fn problem6() -> Expr {
     // 73 bytes
     // B$ L& B. S3/,6%},!-"$!-!.[} B$ v& B$ v& B$ v& SLLLLLLLL L8 B. v8 B. v8 v8
     icfp! {
+        lazy;
         let f = (fn x -> (concat x (concat x x))) in
         (concat
             (#header)
@@ -216,6 +103,7 @@ fn problem8() -> Expr {
     let header = "solve lambdaman8 ";
 
     icfp! {
+        lazy;
         let d = (fn s -> (concat s (concat s s))) in
         let p = (fn s -> (d (d (d (d s))))) in
         (concat (#header) (p (concat (concat (concat (p "DD") (p "LL")) (p "UU")) (p "RR"))))
@@ -225,6 +113,7 @@ fn problem8() -> Expr {
 fn problem19() -> Expr {
     let header = "solve lambdaman19 ";
     icfp! {
+        lazy;
         let y = (fn f -> ((fn x -> (f (x x))) (fn x -> (f (x x))))) in
         (concat (#header)
             (
@@ -259,6 +148,7 @@ fn problem19() -> Expr {
 fn problem20() -> Expr {
     let header = "solve lambdaman20 ";
     icfp! {
+        lazy;
         (concat (#header)
             (
                 (fn r ->
@@ -344,8 +234,9 @@ fn problem1x() -> Expr {
     // 13: B~ L2 B~ LD B. S3/,6%},!-"$!-!.VX} B~ B~ B~ B~ LF B~ LX B~ vF B~ vX vX LX B~ vF B~ vX vX LF LC LS LR ? B= vC I! vR B~ B~ B~ vF B- vC I" B~ v2 vS B. vR B. B~ vD vS B~ vD vS IYX/ I9Ym S LS BT I" BD B/ vS I)%TWQ;bL\3 SFO>L LS B% B* vS I&LR IA33?&-jqQ.
     // 14: B~ L2 B~ LD B. S3/,6%},!-"$!-!.VY} B~ B~ B~ B~ LF B~ LX B~ vF B~ vX vX LX B~ vF B~ vX vX LF LC LS LR ? B= vC I! vR B~ B~ B~ vF B- vC I" B~ v2 vS B. vR B. B~ vD vS B~ vD vS IYX/ I%'d S LS BT I" BD B/ vS I)%TWQ;bL\3 SFO>L LS B% B* vS I&LR IA33?&-jqQ.
     icfp! {
-        let R = (fn s -> (% (* s 48271) 18446744073709551557)) in
-        let d = (fn s -> (take 1 (drop (/ s 4611686018427387904) "LUDR"))) in
+        lazy;
+        let R = (fn s -> (% (* s 48271) 18446744073709551557_u64)) in
+        let d = (fn s -> (take 1 (drop (/ s 4611686018427387904_u64) "LUDR"))) in
         (concat "solve lambdaman15 " (fix (fn f c s r ->
             (if (== c 0) {
                 r
@@ -3,12 +3,11 @@ use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use anyhow::{bail, ensure, Context, Result};
 use clap::{Parser, Subcommand};
 use common::expr::{Expr, Token};
+use common::renumber::renumber_vars;
 use rayon::prelude::*;
 use rng::Rng;
 use simulate::{load_game, MAX_MOVES};
 
-#[macro_use]
-mod assembler;
 pub mod rng;
 pub mod simulate;
 
@@ -120,12 +119,26 @@ fn search_main(problem_id: usize, stride: usize, rng_name: &str, start_seed: u64
     Ok(())
 }
 
+/// Compiles a solution with the shortest variable numbering.
+fn compile_expr(
+    rng: &Rng,
+    problem_id: usize,
+    seed: u64,
+    stride: usize,
+    moves: usize,
+) -> Result<Expr> {
+    let expr = rng.compile_expr(problem_id, seed, stride, moves)?;
+    let renumbered = renumber_vars(&expr);
+    eprintln!("lambdaman{problem_id}: {renumbered}");
+    Ok(renumbered.expr)
+}
+
 fn do_submit(problem_id: usize, expr: &Expr) -> Result<()> {
     let api_token = std::env::var("API_TOKEN").context("API_TOKEN is not set")?;
 
     eprintln!(
         "lambdaman{problem_id}: submitting {}B solution...",
-        expr.encoded().to_string().len()
+        expr.size_in_bytes()
     );
 
     let client = reqwest::blocking::Client::new();
@@ -159,9 +172,9 @@ fn compile_main(
     rng_name: &str,
 ) -> Result<()> {
     let rng = Rng::from_name(rng_name).context("unknown RNG name")?;
-    let expr = rng.compile_expr(problem_id, seed, stride, moves)?;
+    let expr = compile_expr(&rng, problem_id, seed, stride, moves)?;
     println!("{}", expr.encoded());
-    eprintln!("({} bytes)", expr.encoded().to_string().len());
+    eprintln!("({} bytes)", expr.size_in_bytes());
     Ok(())
 }
 
@@ -173,7 +186,7 @@ fn submit_main(
     rng_name: &str,
 ) -> Result<()> {
     let rng = Rng::from_name(rng_name).context("unknown RNG name")?;
-    let expr = rng.compile_expr(problem_id, seed, stride, moves)?;
+    let expr = compile_expr(&rng, problem_id, seed, stride, moves)?;
     do_submit(problem_id, &expr)?;
     Ok(())
 }
@@ -310,10 +323,13 @@ const KNOWN_SOLUTIONS: &[KnownSolution] = &[
 
 fn compile_all_main() -> Result<()> {
     for known in KNOWN_SOLUTIONS {
-        let expr =
-            known
-                .rng
-                .compile_expr(known.problem_id, known.seed, known.stride, known.moves)?;
+        let expr = compile_expr(
+            &known.rng,
+            known.problem_id,
+            known.seed,
+            known.stride,
+            known.moves,
+        )?;
         let code = expr.encoded().to_string();
         eprintln!("lambdaman{}: {} bytes", known.problem_id, code.len());
     }
@@ -322,10 +338,13 @@ fn compile_all_main() -> Result<()> {
 
 fn submit_all_main() -> Result<()> {
     for known in KNOWN_SOLUTIONS {
-        let expr =
-            known
-                .rng
-                .compile_expr(known.problem_id, known.seed, known.stride, known.moves)?;
+        let expr = compile_expr(
+            &known.rng,
+            known.problem_id,
+            known.seed,
+            known.stride,
+            known.moves,
+        )?;
         do_submit(known.problem_id, &expr)?;
     }
     Ok(())
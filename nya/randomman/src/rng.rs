@@ -1,9 +1,8 @@
-use crate::{assembler::ToExpr, simulate::Direction};
+use crate::simulate::Direction;
 
 use anyhow::{bail, Result};
-use common::expr::{BinOp, Expr};
+use common::{expr::Expr, icfp};
 use rand::Rng as _;
-use std::rc::Rc;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum Rng {
@@ -71,15 +70,19 @@ impl Rng {
         // RNG expression takes `s` as an argument.
         match self {
             Self::Default | Self::DefaultRev => icfp! {
-                (% (* s 48271) 18446744073709551557)
+                lazy;
+                (% (* s 48271) 18446744073709551557_u128)
             },
             Self::Better => icfp! {
-                (% (+ (* s 0xd1342543de82ef95) 1) 18446744073709551616)
+                lazy;
+                (% (+ (* s 0xd1342543de82ef95_u128) 1) 18446744073709551616_u128)
             },
             Self::MediumModRev => icfp! {
+                lazy;
                 (% (* s 48271) 2147483647)
             },
             Self::SmallModRev => icfp! {
+                lazy;
                 (% (* s 48271) 830579)
             },
             Self::Reference => panic!("reference RNG is not compilable"),
@@ -111,7 +114,7 @@ impl Rng {
         }
         let last_seed = last_seed as u128;
 
-        let div = match self {
+        let div: u128 = match self {
             Self::SmallModRev => 207645,
             Self::MediumModRev => 536870912,
             Self::Better | Self::Default | Self::DefaultRev => 4611686018427387904,
@@ -119,14 +122,15 @@ impl Rng {
         };
 
         let step_expr = match stride {
-            1 => icfp! { (take 1 (drop (/ s (#div)) "LUDR")) },
-            2 => icfp! { (take 2 (drop (* (/ s (#div)) 2) "LLUUDDRR")) },
+            1 => icfp! { lazy; (take 1 (drop (/ s (#div)) "LUDR")) },
+            2 => icfp! { lazy; (take 2 (drop (* (/ s (#div)) 2) "LLUUDDRR")) },
             _ => bail!("unsupported stride: {stride}"),
         };
 
         let expr = match self {
             // ***HELP ME***: Optimize this code.
             Self::Default | Self::Better => icfp! {
+                lazy;
                 (concat (#header) (fix (fn f s ->
                     (if (== s (#last_seed)) {
                         ""
@@ -138,11 +142,13 @@ impl Rng {
             Self::DefaultRev | Self::MediumModRev | Self::SmallModRev => {
                 if steps <= 65536 {
                     icfp! {
+                        lazy;
                         let d = (fn f x -> (f (f x))) in
                         (d d d d (fn f s -> (concat (f (#rng_expr)) (#step_expr))) (fn x -> (#header)) (#seed))
                     }
                 } else {
                     icfp! {
+                        lazy;
                         (fix (fn f s ->
                             (if (== s (#seed)) {
                                 (#header)
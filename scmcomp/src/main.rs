@@ -1,10 +1,12 @@
 use anyhow::{bail, ensure};
 use common::{
     compiler::{compile::compile_to_lambda, parser::parse},
-    eval::eval,
+    eval::{eval, eval_with_stats, EvalOptions},
     expr::Expr,
     lambdaman::map::LMap,
     optimize::optimize,
+    renumber::renumber_vars,
+    typecheck::typecheck,
 };
 use std::io::Read;
 
@@ -37,9 +39,16 @@ fn compile() -> anyhow::Result<()> {
 
     let expr = compile_to_lambda(input)?;
 
-    let icfp = expr.icfp();
+    let icfp_prog = minimize(&expr.icfp().join(" "))?;
 
-    println!("{}", icfp.join(" "));
+    if let Err(err) = typecheck_prog(&icfp_prog) {
+        eprintln!("*** The compiled program is ill-typed: {err} ***");
+    }
+    if let Err(err) = check(&icfp_prog) {
+        eprintln!("*** Failed to evaluate the compiled program: {err} ***");
+    }
+
+    println!("{}", icfp_prog);
 
     Ok(())
 }
@@ -48,6 +57,9 @@ fn compile() -> anyhow::Result<()> {
 fn submit(
     #[opt(long, default_value = "")] mut api_token: String,
     #[opt(long)] nolambdaman: bool,
+    // Submits ill-typed programs, e.g. ones relying on self-application other
+    // than fixed-point combinators.
+    #[opt(long)] no_typecheck: bool,
 ) -> anyhow::Result<()> {
     if api_token.is_empty() {
         api_token = get_api_token_from_env();
@@ -60,10 +72,19 @@ fn submit(
 
     let expr = compile_to_lambda(input)?;
 
-    let icfp = expr.icfp();
-
-    let icfp_prog = icfp.join(" ");
+    let icfp_prog = minimize(&expr.icfp().join(" "))?;
     eprintln!("compiled ({} bytes): {}", icfp_prog.len(), icfp_prog);
+    if let Err(err) = typecheck_prog(&icfp_prog) {
+        if !no_typecheck {
+            bail!("{err}\nPass --no-typecheck to submit it anyway.");
+        }
+        eprintln!("*** The compiled program is ill-typed: {err} ***");
+    }
+    // The local evaluation is only a guide, e.g. for programs close to the
+    // limit, so the server gets the final say.
+    if let Err(err) = check(&icfp_prog) {
+        eprintln!("*** Failed to evaluate the compiled program: {err} ***");
+    }
 
     let client = reqwest::blocking::Client::new();
 
@@ -96,6 +117,41 @@ fn submit(
     Ok(())
 }
 
+/// Optimizes the compiled program and renumbers its variables to make it
+/// shorter.
+fn minimize(icfp_prog: &str) -> anyhow::Result<String> {
+    let expr = optimize(icfp_prog.parse()?);
+    eprintln!(
+        "optimized: {} -> {} bytes",
+        icfp_prog.len(),
+        expr.size_in_bytes()
+    );
+    let renumbered = renumber_vars(&expr);
+    eprintln!("{}", renumbered);
+    Ok(renumbered.expr.encoded().to_string())
+}
+
+/// Type checks the compiled program, which finds most errors without waiting
+/// for the evaluation to reach them.
+fn typecheck_prog(icfp_prog: &str) -> anyhow::Result<()> {
+    let ty = typecheck(&icfp_prog.parse()?)?;
+    eprintln!("type: {ty}");
+    Ok(())
+}
+
+/// Evaluates the compiled program locally and prints its statistics, so that
+/// we know it stays within the server's limits before submitting it.
+fn check(icfp_prog: &str) -> anyhow::Result<()> {
+    let expr: Expr = icfp_prog.parse()?;
+    let options = EvalOptions {
+        max_beta_reductions: Some(10_000_000),
+        ..Default::default()
+    };
+    let (_, stats) = eval_with_stats(&expr, &options)?;
+    eprintln!("{}", stats);
+    Ok(())
+}
+
 fn get_api_token_from_env() -> String {
     std::env::var("API_TOKEN").unwrap_or_default()
 }
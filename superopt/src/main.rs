@@ -0,0 +1,70 @@
+//! Searches for the shortest ICFP expression evaluating to a given string or
+//! integer.
+//!
+//! Sizes are tried in increasing order up to the budget, so the first
+//! expression found is the shortest one in the search space.
+
+mod search;
+
+use std::rc::Rc;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use common::{base94::encode_str, expr::Expr};
+use num_bigint::BigInt;
+use search::{Options, Search};
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// The largest encoded size to try, in bytes.
+    #[arg(long, default_value_t = 24)]
+    budget: usize,
+
+    /// Bounds the evaluation of each candidate.
+    #[arg(long, default_value_t = 10_000)]
+    max_beta_reductions: usize,
+
+    /// The longest substring of the target to try as a string literal.
+    #[arg(long, default_value_t = 4)]
+    max_literal_len: usize,
+
+    /// Reads the target as a decimal integer instead of a string.
+    #[arg(long)]
+    int: bool,
+
+    target: String,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let target = if args.int {
+        let n: BigInt = args.target.parse().context("invalid integer")?;
+        Expr::Int(Rc::new(n))
+    } else {
+        encode_str(&args.target)?;
+        Expr::String(args.target.clone().into())
+    };
+
+    let literal = target.size_in_bytes();
+    eprintln!(
+        "target: {} ({literal} bytes as a literal)",
+        target.surface()
+    );
+
+    let mut search = Search::new(
+        target,
+        &Options {
+            max_beta_reductions: args.max_beta_reductions,
+            max_literal_len: args.max_literal_len,
+        },
+    );
+    for size in 1..=args.budget {
+        if let Some(e) = search.find(size) {
+            eprintln!("found in {size} bytes: {}", e.surface());
+            println!("{}", e.encoded());
+            return Ok(());
+        }
+        eprintln!("size {size}: {} values", search.num_values());
+    }
+    bail!("nothing evaluates to the target in {} bytes", args.budget)
+}
@@ -0,0 +1,466 @@
+//! Enumerative search for the shortest expression evaluating to a value.
+//!
+//! Expressions are enumerated by their encoded size, from the leaves up.
+//! Closed expressions of the base types are evaluated as soon as they are
+//! built, and only the smallest expression for each value is kept, since any
+//! larger one could be replaced by it (observational equivalence). This is
+//! what keeps the search feasible. Expressions referring to variables, i.e.
+//! the bodies of lambdas, cannot be evaluated on their own and are kept as
+//! they are.
+//!
+//! Lambdas take an integer, a string or a string function, which is enough
+//! for `let`s and for passing helpers like `λs. s ++ s` around. There is no
+//! recursion, as the fixed-point combinator alone is larger than the programs
+//! this is meant for.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use common::eval::{eval_with, EvalOptions};
+use common::expr::{BinOp, Expr, UnOp};
+use num_bigint::BigInt;
+
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
+pub enum Ty {
+    Int,
+    Bool,
+    Str,
+    Fun(Rc<Ty>, Rc<Ty>),
+}
+
+impl Ty {
+    fn fun(arg: Ty, ret: Ty) -> Ty {
+        Ty::Fun(arg.into(), ret.into())
+    }
+
+    fn of(value: &Expr) -> Option<Ty> {
+        match value {
+            Expr::Int(_) => Some(Ty::Int),
+            Expr::Bool(_) => Some(Ty::Bool),
+            Expr::String(_) => Some(Ty::Str),
+            _ => None,
+        }
+    }
+}
+
+/// The types of lambda arguments.
+fn arg_types() -> [Ty; 3] {
+    [Ty::Int, Ty::Str, Ty::fun(Ty::Str, Ty::Str)]
+}
+
+/// The ways to build an expression of a base type from smaller ones.
+#[derive(Clone, Debug)]
+enum Form {
+    Un(UnOp, Ty),
+    Bin(BinOp, Ty, Ty),
+    If(Ty),
+    /// Applies a function taking the type.
+    App(Ty),
+}
+
+impl Form {
+    /// The bytes taken by the operator and the separators.
+    fn overhead(&self) -> usize {
+        match self {
+            Form::Un(..) => 3,
+            Form::Bin(..) | Form::If(_) | Form::App(_) => 4,
+        }
+    }
+
+    fn children(&self, ty: &Ty) -> Vec<Ty> {
+        match self {
+            Form::Un(_, t) => vec![t.clone()],
+            Form::Bin(_, l, r) => vec![l.clone(), r.clone()],
+            Form::If(t) => vec![Ty::Bool, t.clone(), t.clone()],
+            Form::App(arg) => vec![Ty::fun(arg.clone(), ty.clone()), arg.clone()],
+        }
+    }
+
+    fn build(&self, children: &[Expr]) -> Expr {
+        let child = |i: usize| Rc::new(children[i].clone());
+        match self {
+            Form::Un(op, _) => Expr::Un(*op, child(0)),
+            Form::Bin(op, _, _) => Expr::Bin(*op, child(0), child(1)),
+            Form::If(_) => Expr::If(child(0), child(1), child(2)),
+            Form::App(_) => Expr::Bin(BinOp::App, child(0), child(1)),
+        }
+    }
+
+    fn all(ty: &Ty) -> Vec<Form> {
+        use BinOp::*;
+        let mut forms = match ty {
+            Ty::Int => {
+                let mut forms = vec![
+                    Form::Un(UnOp::Neg, Ty::Int),
+                    Form::Un(UnOp::StrToInt, Ty::Str),
+                ];
+                for op in [Add, Sub, Mul, Div, Mod] {
+                    forms.push(Form::Bin(op, Ty::Int, Ty::Int));
+                }
+                forms.push(Form::If(Ty::Int));
+                forms
+            }
+            Ty::Bool => {
+                let mut forms = vec![Form::Un(UnOp::Not, Ty::Bool)];
+                for op in [Lt, Gt, Eq] {
+                    forms.push(Form::Bin(op, Ty::Int, Ty::Int));
+                }
+                forms.push(Form::Bin(Eq, Ty::Str, Ty::Str));
+                for op in [And, Or] {
+                    forms.push(Form::Bin(op, Ty::Bool, Ty::Bool));
+                }
+                forms
+            }
+            Ty::Str => vec![
+                Form::Un(UnOp::IntToStr, Ty::Int),
+                Form::Bin(Concat, Ty::Str, Ty::Str),
+                Form::Bin(Take, Ty::Int, Ty::Str),
+                Form::Bin(Drop, Ty::Int, Ty::Str),
+                Form::If(Ty::Str),
+            ],
+            Ty::Fun(..) => return vec![],
+        };
+        forms.extend(arg_types().into_iter().map(Form::App));
+        forms
+    }
+}
+
+/// Calls `f` with every way to split `total` into `parts` positive sizes.
+fn compositions(total: usize, parts: usize, f: &mut impl FnMut(&[usize])) {
+    fn go(total: usize, parts: usize, acc: &mut Vec<usize>, f: &mut impl FnMut(&[usize])) {
+        if parts == 1 {
+            if total > 0 {
+                acc.push(total);
+                f(acc);
+                acc.pop();
+            }
+            return;
+        }
+        for n in 1..total {
+            acc.push(n);
+            go(total - n, parts - 1, acc, f);
+            acc.pop();
+        }
+    }
+    go(total, parts, &mut vec![], f);
+}
+
+/// The encoded size of a variable or a lambda binding it, without the
+/// operand.
+fn var_size(v: usize) -> usize {
+    1 + BigInt::from(v).to_radix_be(94).1.len()
+}
+
+/// Closed expressions with their values.
+type Pool = Rc<Vec<(Expr, Expr)>>;
+
+pub struct Options {
+    pub max_beta_reductions: usize,
+    /// The longest substring of a string target to try as a literal.
+    pub max_literal_len: usize,
+}
+
+pub struct Search {
+    target: Expr,
+    eval_options: EvalOptions,
+    /// Larger strings are dropped, as they are unlikely to lead to the target
+    /// and make the search slow.
+    max_str_len: usize,
+    literals: HashMap<(Ty, usize), Vec<Expr>>,
+    /// Closed expressions of base types with their values, indexed by type and
+    /// size. No two have the same value.
+    closed: HashMap<Ty, Vec<Pool>>,
+    seen: HashSet<Expr>,
+    /// Expressions referring to a variable of the context, and lambdas.
+    open: HashMap<(Vec<Ty>, Ty, usize), Rc<Vec<Expr>>>,
+}
+
+impl Search {
+    /// Starts a search for an expression evaluating to `target`, which must be
+    /// an integer or a string.
+    pub fn new(target: Expr, options: &Options) -> Self {
+        let mut literals: HashMap<(Ty, usize), Vec<Expr>> = HashMap::new();
+        let mut add = |e: Expr| {
+            let size = e.size_in_bytes();
+            let ty = Ty::of(&e).unwrap();
+            let list = literals.entry((ty, size)).or_default();
+            if !list.contains(&e) {
+                list.push(e);
+            }
+        };
+        add(Expr::Bool(true));
+        add(Expr::Bool(false));
+        for n in 0..94 {
+            add(Expr::Int(BigInt::from(n).into()));
+        }
+        add(Expr::String(String::new().into()));
+        let mut max_str_len = 16;
+        match &target {
+            // Negative targets are found by negating their absolute value.
+            Expr::Int(n) => add(Expr::Int(BigInt::from(n.magnitude().clone()).into())),
+            Expr::String(s) => {
+                let chars: Vec<char> = s.chars().collect();
+                for i in 0..chars.len() {
+                    for j in i + 1..=chars.len().min(i + options.max_literal_len) {
+                        add(Expr::String(chars[i..j].iter().collect::<String>().into()));
+                    }
+                }
+                add(target.clone());
+                max_str_len = max_str_len.max(2 * chars.len());
+            }
+            _ => {}
+        }
+        let max_int_bits = match &target {
+            Expr::Int(n) => 2 * n.bits() + 64,
+            _ => 64 * max_str_len as u64,
+        };
+
+        Search {
+            target,
+            eval_options: EvalOptions {
+                max_beta_reductions: Some(options.max_beta_reductions),
+                max_int_bits: Some(max_int_bits),
+                ..Default::default()
+            },
+            max_str_len,
+            literals,
+            closed: HashMap::new(),
+            seen: HashSet::new(),
+            open: HashMap::new(),
+        }
+    }
+
+    /// Returns an expression of exactly `size` bytes evaluating to the target,
+    /// if any. Sizes must be tried in increasing order to find the shortest.
+    pub fn find(&mut self, size: usize) -> Option<Expr> {
+        let ty = Ty::of(&self.target).unwrap();
+        let closed = self.closed(&ty, size);
+        closed
+            .iter()
+            .find(|(_, value)| *value == self.target)
+            .map(|(e, _)| e.clone())
+    }
+
+    /// The number of distinct values found so far.
+    pub fn num_values(&self) -> usize {
+        self.seen.len()
+    }
+
+    fn closed(&mut self, ty: &Ty, size: usize) -> Pool {
+        loop {
+            let done = self.closed.get(ty).map_or(0, Vec::len);
+            if done > size {
+                return self.closed[ty][size].clone();
+            }
+            // Sizes are done in order, so that each value is kept for the
+            // smallest expression. Building an expression only needs smaller
+            // ones.
+            let exprs = self.new_closed(ty, done);
+            self.closed
+                .entry(ty.clone())
+                .or_default()
+                .push(exprs.into());
+        }
+    }
+
+    fn new_closed(&mut self, ty: &Ty, size: usize) -> Vec<(Expr, Expr)> {
+        let mut candidates: Vec<(Expr, Expr)> = self
+            .literals
+            .get(&(ty.clone(), size))
+            .into_iter()
+            .flatten()
+            .map(|e| (e.clone(), e.clone()))
+            .collect();
+
+        for form in Form::all(ty) {
+            let Some(total) = size.checked_sub(form.overhead()) else {
+                continue;
+            };
+            let child_tys = form.children(ty);
+            let mut splits = vec![];
+            compositions(total, child_tys.len(), &mut |sizes| {
+                splits.push(sizes.to_vec())
+            });
+            for sizes in splits {
+                // Each child is given as the expression and what stands for
+                // it in the evaluation: its value, or the lambda itself.
+                let children: Vec<Vec<(Expr, Expr)>> = child_tys
+                    .iter()
+                    .zip(&sizes)
+                    .map(|(t, &n)| match t {
+                        Ty::Fun(..) => self
+                            .open(&[], t, n)
+                            .iter()
+                            .map(|e| (e.clone(), e.clone()))
+                            .collect(),
+                        _ => self.closed(t, n).to_vec(),
+                    })
+                    .collect();
+                product(&children, &mut |picked| {
+                    let exprs: Vec<Expr> = picked.iter().map(|(e, _)| e.clone()).collect();
+                    let values: Vec<Expr> = picked.iter().map(|(_, v)| v.clone()).collect();
+                    candidates.push((form.build(&exprs), form.build(&values)));
+                });
+            }
+        }
+
+        let mut ret = vec![];
+        for (e, shallow) in candidates {
+            let Ok(value) = eval_with(&shallow, &self.eval_options) else {
+                continue;
+            };
+            let too_large =
+                matches!(&value, Expr::String(s) if s.chars().count() > self.max_str_len);
+            if Ty::of(&value).as_ref() == Some(ty) && !too_large && self.seen.insert(value.clone())
+            {
+                ret.push((e, value));
+            }
+        }
+        ret
+    }
+
+    /// Expressions of `size` bytes in `ctx` that refer to one of its
+    /// variables, or lambdas. `ctx` lists the types of the variables, which are
+    /// numbered from 0.
+    fn open(&mut self, ctx: &[Ty], ty: &Ty, size: usize) -> Rc<Vec<Expr>> {
+        let key = (ctx.to_vec(), ty.clone(), size);
+        if let Some(exprs) = self.open.get(&key) {
+            return exprs.clone();
+        }
+
+        let mut exprs = vec![];
+        for (v, t) in ctx.iter().enumerate() {
+            if t == ty && var_size(v) == size {
+                exprs.push(Expr::Var(v));
+            }
+        }
+        if let Ty::Fun(arg, ret) = ty {
+            let v = ctx.len();
+            if let Some(body_size) = size.checked_sub(var_size(v) + 1) {
+                let mut inner = ctx.to_vec();
+                inner.push(arg.as_ref().clone());
+                for body in self.open(&inner, ret, body_size).iter() {
+                    exprs.push(Expr::Lambda(v, body.clone().into()));
+                }
+            }
+        } else if !ctx.is_empty() {
+            for form in Form::all(ty) {
+                let Some(total) = size.checked_sub(form.overhead()) else {
+                    continue;
+                };
+                let child_tys = form.children(ty);
+                let mut splits = vec![];
+                compositions(total, child_tys.len(), &mut |sizes| {
+                    splits.push(sizes.to_vec())
+                });
+                for sizes in splits {
+                    // Closed children have no variables to refer to, so they
+                    // are the deduplicated ones. At least one child must be
+                    // open, or the expression would be closed.
+                    let children: Vec<(Vec<Expr>, Rc<Vec<Expr>>)> = child_tys
+                        .iter()
+                        .zip(&sizes)
+                        .map(|(t, &n)| {
+                            let closed = match t {
+                                Ty::Fun(..) => vec![],
+                                _ => self.closed(t, n).iter().map(|(e, _)| e.clone()).collect(),
+                            };
+                            (closed, self.open(ctx, t, n))
+                        })
+                        .collect();
+                    let choices: Vec<Vec<(Expr, bool)>> = children
+                        .iter()
+                        .map(|(closed, open)| {
+                            let closed = closed.iter().map(|e| (e.clone(), false));
+                            let open = open.iter().map(|e| (e.clone(), true));
+                            closed.chain(open).collect()
+                        })
+                        .collect();
+                    product(&choices, &mut |picked| {
+                        if picked.iter().any(|(_, open)| *open) {
+                            let picked: Vec<Expr> = picked.iter().map(|(e, _)| e.clone()).collect();
+                            exprs.push(form.build(&picked));
+                        }
+                    });
+                }
+            }
+        }
+
+        let exprs = Rc::new(exprs);
+        self.open.insert(key, exprs.clone());
+        exprs
+    }
+}
+
+/// Calls `f` with every combination of one element from each list.
+fn product<T: Clone>(lists: &[Vec<T>], f: &mut impl FnMut(&[T])) {
+    fn go<T: Clone>(lists: &[Vec<T>], acc: &mut Vec<T>, f: &mut impl FnMut(&[T])) {
+        let Some((first, rest)) = lists.split_first() else {
+            f(acc);
+            return;
+        };
+        for x in first {
+            acc.push(x.clone());
+            go(rest, acc, f);
+            acc.pop();
+        }
+    }
+    go(lists, &mut vec![], f);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::eval::eval;
+
+    fn shortest(target: Expr, budget: usize) -> Option<String> {
+        let mut search = Search::new(
+            target.clone(),
+            &Options {
+                max_beta_reductions: 1000,
+                max_literal_len: 4,
+            },
+        );
+        let e = (1..=budget).find_map(|size| search.find(size))?;
+        assert_eq!(eval(&e).unwrap(), target);
+        Some(e.encoded().to_string())
+    }
+
+    fn int(n: i64) -> Expr {
+        Expr::Int(BigInt::from(n).into())
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(shortest(int(5), 10).as_deref(), Some("I&"));
+        assert_eq!(shortest(int(8835), 10).as_deref(), Some("I~~"));
+        let s = Expr::String("abc".to_string().into());
+        assert_eq!(shortest(s, 10).as_deref(), Some("S!\"#"));
+    }
+
+    #[test]
+    fn operators() {
+        // Negative numbers have no literals.
+        assert_eq!(shortest(int(-5), 10).as_deref(), Some("U- I&"));
+        assert_eq!(shortest(int(-94), 10).as_deref(), Some("U- I\"!"));
+        assert_eq!(shortest(int(-1000), 4), None);
+    }
+
+    #[test]
+    fn lambdas() {
+        let mut search = Search::new(
+            int(0),
+            &Options {
+                max_beta_reductions: 1000,
+                max_literal_len: 4,
+            },
+        );
+        let id = search.open(&[], &Ty::fun(Ty::Str, Ty::Str), 5);
+        assert_eq!(*id, vec!["L! v!".parse().unwrap()]);
+        // Bodies must refer to their variable, as constant functions would
+        // only make the search larger.
+        let double = search.open(&[], &Ty::fun(Ty::Str, Ty::Str), 11);
+        assert!(double.contains(&"L! B. v! v!".parse().unwrap()));
+        assert!(!double.contains(&"L! B. S! S!".parse().unwrap()));
+    }
+}
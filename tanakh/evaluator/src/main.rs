@@ -1,38 +1,26 @@
-use std::{io::Write as _, path::PathBuf, rc::Rc};
+use std::{
+    io::Write as _,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
 use common::{
     eval,
-    expr::{tokenize, BinOp, Expr, UnOp},
+    expr::{parse_reader, parse_surface, BinOp, Expr, UnOp},
+    typecheck::is_fix,
 };
 
-fn is_fix(e: &Expr) -> bool {
-    let check = |e: &Expr, f: usize| -> bool {
-        match e {
-            Expr::Lambda(x, e) => match e.as_ref() {
-                Expr::Bin(BinOp::App, l, r) => {
-                    matches!(l.as_ref(), Expr::Var(v) if *v == f)
-                        && matches!(r.as_ref(), Expr::Bin(BinOp::App, v1, v2) if matches!(v1.as_ref(), Expr::Var(v1) if v1 == x) && matches!(v2.as_ref(), Expr::Var(v2) if v2 == x))
-                }
-                _ => false,
-            },
-            _ => false,
-        }
-    };
+fn pp(e: &Expr) -> String {
+    pp_depth(e, usize::MAX)
+}
 
-    match e {
-        Expr::Lambda(f, e) => match e.as_ref() {
-            Expr::Bin(BinOp::App, l, r) => {
-                let l = check(&l, *f);
-                let r = check(&r, *f);
-                l && r
-            }
-            _ => false,
-        },
-        _ => false,
+/// Pretty-prints `e`, eliding subterms nested deeper than `depth` as `...`.
+fn pp_depth(e: &Expr, depth: usize) -> String {
+    if depth == 0 {
+        return "...".to_string();
     }
-}
+    let pp = |e: &Expr| pp_depth(e, depth - 1);
 
-fn pp(e: &Expr) -> String {
     if is_fix(e) {
         return "fix".to_string();
     }
@@ -71,6 +59,8 @@ fn pp(e: &Expr) -> String {
                 BinOp::Take => format!("take ({l}) ({r})"),
                 BinOp::Drop => format!("drop ({l}) ({r})"),
                 BinOp::App => format!("({l} {r})"),
+                BinOp::AppL => format!("({l} ~{r})"),
+                BinOp::AppV => format!("({l} !{r})"),
                 _ => unreachable!(),
             }
         }
@@ -308,20 +298,49 @@ fn to_scheme(e: &Expr) -> String {
     }
 }
 
+/// Parses the program in the file. Parse errors are shown with the offending
+/// part of the file.
+fn read_expr(path: &Path) -> anyhow::Result<Expr> {
+    let file = std::io::BufReader::new(std::fs::File::open(path)?);
+    match parse_reader(file) {
+        Ok((expr, _)) => Ok(expr),
+        Err(err) => match std::fs::read(path) {
+            Ok(source) => {
+                let source = String::from_utf8_lossy(&source);
+                anyhow::bail!("{}:\n{}", path.display(), err.diagnostic(&source))
+            }
+            Err(_) => Err(err.into()),
+        },
+    }
+}
+
 #[argopt::subcmd]
 fn pretty(path: PathBuf) -> anyhow::Result<()> {
-    let s = std::fs::read_to_string(&path)?;
-    let tokens = tokenize(&s)?;
-    let expr = Expr::parse_tokens(&tokens)?;
+    let expr = read_expr(&path)?;
     println!("{}", pp(&expr));
     Ok(())
 }
 
+/// Prints the program in the readable syntax, which `encode` reads back.
+#[argopt::subcmd]
+fn decode(path: PathBuf) -> anyhow::Result<()> {
+    let expr = read_expr(&path)?;
+    println!("{}", expr.surface());
+    Ok(())
+}
+
+/// Encodes a program written in the readable syntax.
+#[argopt::subcmd]
+fn encode(path: PathBuf) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(&path)?;
+    let expr = parse_surface(&source).map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+    println!("{}", expr.encoded());
+    Ok(())
+}
+
 #[argopt::subcmd]
 fn scheme(path: PathBuf) -> anyhow::Result<()> {
-    let s = std::fs::read_to_string(&path)?;
-    let tokens = tokenize(&s)?;
-    let expr = Expr::parse_tokens(&tokens)?;
+    let expr = read_expr(&path)?;
     println!("(use srfi-13)");
     println!("(print (force {}))", to_scheme(&expr));
     Ok(())
@@ -329,15 +348,65 @@ fn scheme(path: PathBuf) -> anyhow::Result<()> {
 
 #[argopt::subcmd]
 fn haskell(path: PathBuf) -> anyhow::Result<()> {
-    let s = std::fs::read_to_string(&path)?;
-    let tokens = tokenize(&s)?;
-    let expr = Expr::parse_tokens(&tokens)?;
+    let expr = read_expr(&path)?;
     let expr = simplify_comb(&expr);
     println!("import Control.Monad.Fix");
     println!("main = print $ {}", to_haskell(&expr));
     Ok(())
 }
 
+/// Prints each reduction step of the program, with the rule applied, the
+/// position of the redex and the redex itself.
+#[argopt::subcmd]
+fn trace(
+    path: PathBuf,
+    /// Stop after this many steps
+    #[opt(long)]
+    max_steps: Option<usize>,
+    /// Elide subterms nested deeper than this when printing
+    #[opt(long)]
+    depth: Option<usize>,
+    /// Print the whole term after each step instead of the redex
+    #[opt(long)]
+    term: bool,
+) -> anyhow::Result<()> {
+    let expr = read_expr(&path)?;
+    let depth = depth.unwrap_or(usize::MAX);
+
+    let mut stepper = eval::Stepper::new(expr);
+    println!("{}", pp_depth(&stepper.expr(), depth));
+    loop {
+        if max_steps.is_some_and(|max_steps| stepper.steps() >= max_steps) {
+            println!("stopped after {} steps", stepper.steps());
+            return Ok(());
+        }
+        let step = match stepper.step() {
+            Ok(Some(step)) => step,
+            Ok(None) => break,
+            Err(e) => {
+                println!("error after {} steps: {e}", stepper.steps());
+                return Ok(());
+            }
+        };
+        let path = step
+            .path
+            .iter()
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(".");
+        let shown = if term { stepper.expr() } else { step.redex };
+        println!(
+            "#{} {} at [{path}]: {}",
+            stepper.steps(),
+            step.rule,
+            pp_depth(&shown, depth)
+        );
+    }
+    println!("=== result after {} steps ===", stepper.steps());
+    println!("{}", pp(&stepper.expr()));
+    Ok(())
+}
+
 #[argopt::subcmd]
 fn repl() -> anyhow::Result<()> {
     let mut stdout = std::io::stdout();
@@ -364,7 +433,7 @@ fn repl() -> anyhow::Result<()> {
     }
 }
 
-#[argopt::cmd_group(commands = [scheme, haskell, repl, pretty])]
+#[argopt::cmd_group(commands = [scheme, haskell, repl, pretty, trace, decode, encode])]
 fn main() -> anyhow::Result<()> {
     env_logger::init();
 }
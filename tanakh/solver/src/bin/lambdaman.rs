@@ -1,6 +1,6 @@
-use std::{path::PathBuf, rc::Rc};
+use std::path::PathBuf;
 
-use common::expr::{BinOp, Expr};
+use common::{expr::Expr, icfp};
 use num_bigint::BigInt;
 
 fn compress(sol: &str) -> BigInt {
@@ -24,125 +24,6 @@ fn compress(sol: &str) -> BigInt {
     ret
 }
 
-trait ToExpr {
-    fn to_expr(&self) -> Expr;
-}
-
-impl ToExpr for BigInt {
-    fn to_expr(&self) -> Expr {
-        Expr::Int(self.clone().into())
-    }
-}
-
-impl ToExpr for String {
-    fn to_expr(&self) -> Expr {
-        Expr::String(self.clone().into())
-    }
-}
-
-impl ToExpr for &str {
-    fn to_expr(&self) -> Expr {
-        Expr::String(self.to_string().into())
-    }
-}
-
-impl ToExpr for i32 {
-    fn to_expr(&self) -> Expr {
-        Expr::Int(BigInt::from(*self).into())
-    }
-}
-
-impl ToExpr for i64 {
-    fn to_expr(&self) -> Expr {
-        Expr::Int(BigInt::from(*self).into())
-    }
-}
-
-impl ToExpr for i128 {
-    fn to_expr(&self) -> Expr {
-        Expr::Int(BigInt::from(*self).into())
-    }
-}
-
-impl ToExpr for Expr {
-    fn to_expr(&self) -> Expr {
-        self.clone()
-    }
-}
-
-macro_rules! icfp {
-    (fix $($args:tt)+) => {
-        icfp! {
-            (fn f -> ((fn x -> (f (x x))) (fn x -> (f (x x))))) $($args)*
-        }
-    };
-    (fn $($args:ident)+ -> $body:tt) => {{
-        let mut e = icfp!{ $body };
-        for arg in [$(stringify!($args)), *].iter().rev() {
-            e = Expr::Lambda(varid(arg), Box::new(e));
-        }
-        e
-    }};
-    (let $var:ident = $val:tt in $($body:tt)+) => {
-        Expr::Bin(BinOp::App,
-            Box::new(Expr::Lambda(varid(stringify!($var)), Box::new(icfp!{ $($body)* }))),
-            Box::new(icfp!{ $val }))
-    };
-    (if $cond:tt { $($th:tt)+ } else { $($el:tt)+ }) => {
-        Expr::If(Box::new(icfp!{ $cond }), Box::new(icfp!{ $($th)* }), Box::new(icfp!{ $($el)* }))
-    };
-    (concat $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Concat, Box::new(icfp!{ $a1 }), Box::new(icfp!{ $a2 }))
-    };
-    (take $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Take, Box::new(icfp!{ $a1 }), Box::new(icfp!{ $a2 }))
-    };
-    (drop $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Drop, Box::new(icfp!{ $a1 }), Box::new(icfp!{ $a2 }))
-    };
-    (== $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Eq, Box::new(icfp!{ $a1 }), Box::new(icfp!{ $a2 }))
-    };
-    (+ $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Add, Box::new(icfp!{ $a1 }), Box::new(icfp!{ $a2 }))
-    };
-    (- $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Sub, Box::new(icfp!{ $a1 }), Box::new(icfp!{ $a2 }))
-    };
-    (* $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Mul, Box::new(icfp!{ $a1 }), Box::new(icfp!{ $a2 }))
-    };
-    (/ $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Div, Box::new(icfp!{ $a1 }), Box::new(icfp!{ $a2 }))
-    };
-    (% $a1:tt $a2:tt) => {
-        Expr::Bin(BinOp::Mod, Box::new(icfp!{ $a1 }), Box::new(icfp!{ $a2 }))
-    };
-    (# $var:ident) => {
-        ToExpr::to_expr(&$var)
-    };
-    ($f:tt $($args:tt)+) => {{
-        let mut e = icfp!{ $f };
-        $(
-            e = Expr::Bin(BinOp::App, Box::new(e), Box::new(icfp!{ $args }));
-        )+
-        e
-    }};
-    ($var:ident) => {
-        Expr::Var(varid(stringify!($var)))
-    };
-    ($val:literal) => {
-        ToExpr::to_expr(&$val)
-    };
-    (($($tt:tt)+)) => {
-        icfp! { $($tt)+ }
-    };
-}
-
-fn varid(s: &str) -> usize {
-    s.chars().next().unwrap() as usize - 'a' as usize
-}
-
 fn problem6() -> Expr {
     let header = "solve lambdaman6 ";
     icfp! {